@@ -0,0 +1,68 @@
+//!Throughput of inserting clock commands, before and after ClockRuster kept its connection
+//! cargo bench --bench insert_throughput
+//! CLOCKRUST_BENCH_COMMANDS sets how many commands each run inserts, 100k by default
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use chrono::{TimeZone, Utc};
+
+use clockrusting::command::{Command, CommandType};
+use clockrusting::db::ClockRuster;
+use clockrusting::error::ClockError;
+
+fn commands(count: usize) -> Vec<Command> {
+    let start = Utc.with_ymd_and_hms(2022, 1, 31, 0, 0, 0).unwrap();
+    (0..count)
+        .map(|i| {
+            let cmd_type = if i % 2 == 0 { CommandType::ClockIn } else { CommandType::ClockOut };
+            Command::new(cmd_type, start + chrono::Duration::seconds(i as i64), format!("bench task {}", i % 10))
+        })
+        .collect()
+}
+
+///Time f against a fresh file in the temp dir
+fn run(name: &str, cmds: &[Command], f: impl FnOnce(&str, &[Command]) -> Result<(), ClockError>) -> Result<(), ClockError> {
+    let db = bench_db();
+    let _ = std::fs::remove_file(&db);
+    let started = Instant::now();
+    let result = f(path_str(&db), cmds);
+    let elapsed = started.elapsed();
+    let _ = std::fs::remove_file(&db);
+    result?;
+    report(name, cmds.len(), elapsed);
+    Ok(())
+}
+
+fn bench_db() -> PathBuf {
+    std::env::temp_dir().join(format!("clock_rust_bench_{}", std::process::id()))
+}
+
+fn path_str(path: &Path) -> &str {
+    path.to_str().expect("temp dir is not valid UTF-8")
+}
+
+fn report(name: &str, count: usize, elapsed: Duration) {
+    println!("{:<50} {:>8} commands in {:>9.3}s = {:>10.0} commands/s",
+             name, count, elapsed.as_secs_f64(), count as f64 / elapsed.as_secs_f64());
+}
+
+fn main() -> Result<(), ClockError> {
+    let count = std::env::var("CLOCKRUST_BENCH_COMMANDS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .unwrap_or(100_000);
+    let cmds = commands(count);
+
+    //not the old code, but the same work it did per call: open the file, check the schema, insert and commit
+    run("simulated before: reopening the file per command", &cmds, |db, cmds| {
+        cmds.iter().try_for_each(|cmd| ClockRuster::init(db).run_clock_command(cmd))
+    })?;
+    run("after: kept connection, commit per command", &cmds, |db, cmds| {
+        let cr = ClockRuster::init(db);
+        cmds.iter().try_for_each(|cmd| cr.run_clock_command(cmd))
+    })?;
+    run("after: kept connection, one transaction", &cmds, |db, cmds| {
+        let cr = ClockRuster::init(db);
+        cr.transaction(|tx| cmds.iter().try_for_each(|cmd| tx.run_clock_command(cmd)))
+    })?;
+    Ok(())
+}
@@ -1,18 +1,29 @@
-use std::fmt::{Display, Error, Formatter};
-use chrono::{DateTime, Utc};
+use std::fmt::{Display, Formatter};
+use chrono::{DateTime, Duration, Local, NaiveTime, SecondsFormat, TimeZone, Utc};
 use std::hash::{ Hash,Hasher };
 use std::str::FromStr;
+use serde::{Deserialize, Serialize};
 
-use color_eyre::{eyre::eyre, Report, Result};
 use tracing::{info};
 
-const COMMAND_EG: &str = "clock-in::2021-10-31T04:10:29.316132167Z::'task description'";
+use crate::error::ClockError;
+
+pub const COMMAND_EG: &str = "clock-in::2021-10-31T04:10:29.316132167Z::'task description'";
 
 ///Available commands
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum CommandType {
     ClockIn,
     ClockOut,
+    ///Stop the clock on a task without closing it
+    Pause,
+    ///Start the clock again on a paused task
+    Resume,
+    ///Clock out of every open task and clock in to this one
+    Switch,
+    ///Add a note to the task's open session, without changing anything else
+    Note,
 }
 
 impl Display for CommandType {
@@ -20,19 +31,27 @@ impl Display for CommandType {
         match self {
             CommandType::ClockIn => write!(f, "clock-in"),
             CommandType::ClockOut => write!(f, "clock-out"),
+            CommandType::Pause => write!(f, "pause"),
+            CommandType::Resume => write!(f, "resume"),
+            CommandType::Switch => write!(f, "switch"),
+            CommandType::Note => write!(f, "note"),
         }
     }
 }
 
 impl FromStr for CommandType{
-    type Err = Error;
+    type Err = ClockError;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
 
         match &s.to_uppercase()[..]{
             "CLOCKIN" | "CLOCK-IN" => Ok(Self::ClockIn),
             "CLOCKOUT" | "CLOCK-OUT" => Ok(Self::ClockOut),
-            _ => Err(Error),
+            "PAUSE" => Ok(Self::Pause),
+            "RESUME" => Ok(Self::Resume),
+            "SWITCH" => Ok(Self::Switch),
+            "NOTE" => Ok(Self::Note),
+            _ => Err(ClockError::UnknownCommand(s.to_string())),
         }
     }
 }
@@ -46,11 +65,23 @@ impl Hash for CommandType{
 }
 
 ///Struct representing commands to track time
-// #[derive(Hash)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Command {
+    ///Id the store keeps the command under, None until it has been stored
+    /// Stores assign their own when inserting, whatever this is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
     pub command: CommandType,
     pub cmd_datetime: DateTime<Utc>,
     pub task:  String,
+    ///Free text about what was done
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    ///Put in by clockrusting itself rather than asked for, eg a clock-out closing a stale session
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub system: bool,
 }
 
 ///Same task - same hash
@@ -62,14 +93,27 @@ impl Hash for Command{
     }
 }
 
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
 
+///Hash we key a task by in storage
+/// 64-bit FNV-1a over the UTF-8 bytes of the task, stored as i64
+/// Unlike DefaultHasher this is fixed forever - changing it orphans every stored row
+pub fn task_hash(task: &str) -> i64 {
+    task.bytes()
+        .fold(FNV_OFFSET_BASIS, |hash, byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME)) as i64
+}
 
 impl Command {
     pub fn new(cmd: CommandType, cmd_datetime:DateTime<Utc>, task: String) -> Self {
         Self {
+            id: None,
             command: cmd,
             cmd_datetime,
             task,
+            note: None,
+            tags: Vec::new(),
+            system: false,
         }
     }
 
@@ -77,6 +121,23 @@ impl Command {
         self.cmd_datetime.to_rfc3339()
     }
 
+    ///The command in the form create_command reads, which always parses back to the same command but for its id
+    /// Ids are the store's, a parsed command has none until it is stored
+    /// eg clock-in::2021-10-31T04:10:29.316132167Z::'task description'::+docs::note='first draft'
+    pub fn to_command_string(&self) -> String {
+        let mut cmd_str = format!("{}::{}::{}", self.command, self.cmd_datetime.to_rfc3339_opts(SecondsFormat::AutoSi, true), quote(&self.task));
+        for tag in &self.tags {
+            cmd_str.push_str(&format!("::+{}", quote(tag)));
+        }
+        if let Some(note) = &self.note {
+            cmd_str.push_str(&format!("::note={}", quote(note)));
+        }
+        if self.system {
+            cmd_str.push_str("::system");
+        }
+        cmd_str
+    }
+
     //For when we want to print out our time report
     // pub fn key(&self){
     //
@@ -90,42 +151,257 @@ impl Display for Command {
 }
 
 
+///Understand the time of a command, relative to now
+/// rfc3339 is the canonical form, eg 2021-10-31T04:10:29Z
+/// `now`, offsets from now such as `-15m`, `-1h30m` or `+2h` (units d, h, m and s)
+/// and wall-clock times `09:30`, `17:00:15`, `today 09:30` or `yesterday 17:00` in now's timezone
+/// A wall-clock time skipped by a DST change is refused, one that happens twice is taken the first time
+pub fn parse_time<Tz: TimeZone>(time_str: &str, now: &DateTime<Tz>) -> Result<DateTime<Utc>, ClockError> {
+    let bad = |reason: &str| ClockError::BadTimestamp { input: time_str.to_string(), reason: reason.to_string() };
+    let trimmed = time_str.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(trimmed) {
+        return Ok(dt.with_timezone(&Utc));
+    }
+    if trimmed.eq_ignore_ascii_case("now") {
+        return Ok(now.with_timezone(&Utc));
+    }
+    if trimmed.starts_with(['-', '+']) {
+        let offset = parse_offset(trimmed).ok_or_else(|| bad("offsets look like -15m or -1h30m"))?;
+        return now.with_timezone(&Utc).checked_add_signed(offset).ok_or_else(|| bad("that is further away than any time we can keep"));
+    }
+
+    let (days_back, clock) = match trimmed.split_once(' ') {
+        Some((day, clock)) if day.eq_ignore_ascii_case("today") => (0, clock.trim()),
+        Some((day, clock)) if day.eq_ignore_ascii_case("yesterday") => (1, clock.trim()),
+        Some(_) => return Err(bad("not rfc3339, now, an offset or a wall-clock time")),
+        None => (0, trimmed),
+    };
+    let time = NaiveTime::parse_from_str(clock, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(clock, "%H:%M"))
+        .map_err(|_| bad("not rfc3339, now, an offset or a wall-clock time"))?;
+    let day = now.date_naive() - Duration::days(days_back);
+    match now.timezone().from_local_datetime(&day.and_time(time)).earliest() {
+        Some(dt) => Ok(dt.with_timezone(&Utc)),
+        None => Err(bad("that time is skipped by a daylight saving change")),
+    }
+}
+
+///Length of time such as 12h or 1h30m, in the units offsets take
+pub fn parse_duration(duration_str: &str) -> Option<Duration> {
+    parse_offset(&format!("+{}", duration_str.trim()))
+}
+
+///Signed offset such as -1h30m, None for one too large to hold
+fn parse_offset(offset: &str) -> Option<Duration> {
+    let (sign, units) = offset.split_at(1);
+    let mut total = Duration::zero();
+    let mut digits = String::new();
+    for c in units.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let amount: i64 = std::mem::take(&mut digits).parse().ok()?;
+        let part = match c {
+            'd' => Duration::try_days(amount)?,
+            'h' => Duration::try_hours(amount)?,
+            'm' => Duration::try_minutes(amount)?,
+            's' => Duration::try_seconds(amount)?,
+            _ => return None,
+        };
+        total = total.checked_add(&part)?;
+    }
+    //a number without a unit, or no units at all
+    if !digits.is_empty() || units.is_empty() {
+        return None;
+    }
+    Some(if sign == "-" { -total } else { total })
+}
+
 ///Create a command from string in following format
-/// COMMAND-TYPE::TIME::DESCRIPTION
-/// where command-type is 'clock-in' or 'clock-out'
-/// TIME is rfc3339 time string
-/// DESCRIPTION is the description of the task to be tracked
-pub fn create_command(check_str: &str) -> Result<Command, Report> {
-
-    // let task = split.as_str();
-    let parts:Vec<&str> = check_str.split("::").collect();
-    let cmd = match parts[0]   {
+/// COMMAND-TYPE::TIME::DESCRIPTION[::FIELD]...
+/// where command-type is 'clock-in', 'clock-out', 'pause', 'resume', 'switch' or 'note'
+/// TIME is rfc3339 time string, or anything else parse_time understands, relative to now in the local timezone
+/// DESCRIPTION is the description of the task to be tracked, quoted with ' or " or bare
+/// FIELD is a tag, `+tag`, a note, `note=text`, or `system` for a command clockrusting put in itself,
+/// and can be given more than once for tags
+/// Inside quotes anything goes but the closing quote, anywhere a backslash takes the next character as it is,
+/// so `fix std\::fs bug` and `'fix std::fs bug'` are both the task `fix std::fs bug`
+pub fn create_command(check_str: &str) -> Result<Command, ClockError> {
+    create_command_at(check_str, &Local::now())
+}
+
+///Create a command as create_command does, with relative and wall-clock times taken from `now`
+pub fn create_command_at<Tz: TimeZone>(check_str: &str, now: &DateTime<Tz>) -> Result<Command, ClockError> {
+    let mut parser = CommandParser::new(check_str);
+
+    let type_str = parser.raw();
+    let cmd = match type_str.as_str() {
         "clock-in" => CommandType::ClockIn ,
         "clock-out" =>  CommandType::ClockOut ,
+        "pause" => CommandType::Pause,
+        "resume" => CommandType::Resume,
+        "switch" => CommandType::Switch,
+        "note" => CommandType::Note,
         //unsupported command
-        _ => return Err(eyre!("Fail, available commands: clock-in | clock-out, eg {}", COMMAND_EG)),
+        _ => return Err(ClockError::UnknownCommand(type_str)),
     };
+    parser.delimiter("expected '::' and a time after the command type")?;
 
-    if parts.len()!=3 {
-        return Err(eyre!("FAIL, usage command::time::title, eg {}", COMMAND_EG))
-    }
-    let time_str = parts[1];
+    let time_str = parser.raw();
     info!("Here is the  TIME STRING: {} ", time_str);
-    let task = parts[2];
+    parser.delimiter("expected '::' and a task after the time")?;
     //let's get chronological
-    let dtime = match DateTime::parse_from_rfc3339(time_str){
-        Ok(dt) => { dt}
-        Err(why) => { return Err(eyre!("ParseError: {}\n FAIL: please supply datetime in rfc3339 format, eg: { }", why, COMMAND_EG))}
-    };
+    let dtime = parse_time(&time_str, now)?;
+
+    let task = parser.value()?;
+    if task.is_empty() {
+        return Err(parser.malformed("expected a task"));
+    }
+    let mut command = Command::new(cmd, dtime, task);
 
-    Ok(Command::new(cmd, dtime.with_timezone(&Utc), String::from(task)))
-    
+    while parser.at_delimiter() {
+        parser.delimiter("")?;
+        let field_start = parser.pos;
+        if parser.eat("+") {
+            let tag = parser.value()?;
+            if tag.is_empty() {
+                return Err(parser.malformed("expected a tag after '+'"));
+            }
+            //a tag given twice is still one tag
+            if !command.tags.contains(&tag) {
+                command.tags.push(tag);
+            }
+        } else if parser.eat("note=") {
+            if command.note.is_some() {
+                return Err(parser.malformed_at(field_start, "only one note allowed"));
+            }
+            command.note = Some(parser.value()?);
+        } else if parser.eat("system") {
+            if !parser.at_end() && !parser.at_delimiter() {
+                return Err(parser.malformed("expected '::' or the end after system"));
+            }
+            command.system = true;
+        } else {
+            return Err(parser.malformed("expected +tag or note=text"));
+        }
+    }
+    if command.command == CommandType::Note && command.note.is_none() {
+        return Err(parser.malformed("a note command needs note=text"));
+    }
+    Ok(command)
+}
+
+///Walks a command string, keeping track of where we are for error messages
+struct CommandParser<'a> {
+    input: &'a str,
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl<'a> CommandParser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { input, chars: input.chars().collect(), pos: 0 }
+    }
+
+    fn malformed(&self, reason: &str) -> ClockError {
+        self.malformed_at(self.pos, reason)
+    }
+
+    fn malformed_at(&self, pos: usize, reason: &str) -> ClockError {
+        ClockError::MalformedCommand { input: self.input.to_string(), column: pos + 1, reason: reason.to_string() }
+    }
+
+    fn at_delimiter(&self) -> bool {
+        self.chars[self.pos..].starts_with(&[':', ':'])
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos == self.chars.len()
+    }
+
+    ///Step past `expected` if it comes next
+    fn eat(&mut self, expected: &str) -> bool {
+        let expected: Vec<char> = expected.chars().collect();
+        if self.chars[self.pos..].starts_with(&expected) {
+            self.pos += expected.len();
+            return true;
+        }
+        false
+    }
+
+    fn delimiter(&mut self, reason: &str) -> Result<(), ClockError> {
+        if self.eat("::") { Ok(()) } else { Err(self.malformed(reason)) }
+    }
+
+    ///Everything up to the next `::`, as it is
+    fn raw(&mut self) -> String {
+        let mut raw = String::new();
+        while !self.at_end() && !self.at_delimiter() {
+            raw.push(self.chars[self.pos]);
+            self.pos += 1;
+        }
+        raw
+    }
+
+    ///A quoted or bare value, unescaped, up to the next `::`
+    fn value(&mut self) -> Result<String, ClockError> {
+        let mut value = String::new();
+        let quote = match self.chars.get(self.pos) {
+            Some(&c) if c == '\'' || c == '"' => {
+                self.pos += 1;
+                Some(c)
+            }
+            _ => None,
+        };
+        loop {
+            let c = match self.chars.get(self.pos) {
+                Some(&c) => c,
+                None if quote.is_some() => return Err(self.malformed("missing closing quote")),
+                None => break,
+            };
+            if quote.is_none() && self.at_delimiter() {
+                break;
+            }
+            self.pos += 1;
+            match c {
+                '\\' => match self.chars.get(self.pos) {
+                    Some(&escaped) => {
+                        value.push(escaped);
+                        self.pos += 1;
+                    }
+                    None => return Err(self.malformed("nothing to escape after '\\'")),
+                },
+                c if Some(c) == quote => {
+                    if !self.at_end() && !self.at_delimiter() {
+                        return Err(self.malformed("expected '::' or the end after the closing quote"));
+                    }
+                    break;
+                }
+                c => value.push(c),
+            }
+        }
+        Ok(value)
+    }
+}
+
+///Quote a value so CommandParser::value reads it back exactly
+fn quote(value: &str) -> String {
+    let mut quoted = String::from("'");
+    for c in value.chars() {
+        if c == '\'' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::hash_map::DefaultHasher;
     use crate::config;
+    use crate::db::ClockRuster;
 
     use super::*;
 
@@ -137,17 +413,17 @@ mod tests {
         let result = create_command("badcommand");
         let report = result.err().unwrap();
         println!("{}", report);
-        assert!(report.to_string().starts_with("Fail, available commands: clock-in | clock-out, eg"));
+        assert!(matches!(report, ClockError::UnknownCommand(ref given) if given == "badcommand"));
+        assert!(report.to_string().starts_with("Fail, available commands: clock-in | clock-out | pause | resume | switch | note, eg"));
     }
 
     #[test]
     fn test_create_clock_in() {
         config::setup_test_logging();
         match create_command("clock-in::2021-12-20T20:22:29.52Z::this is a test"){
-            Ok(Command{ command: _, task, cmd_datetime:_}) => { assert_eq!(task.to_string(), "this is a test") }
+            Ok(Command{ command: _, task, cmd_datetime:_, ..}) => { assert_eq!(task.to_string(), "this is a test") }
             Err(why) => {
-                println!("We have FAILED: {}", why);
-                assert!(false);//let it end
+                panic!("We have FAILED: {}", why);
             }
         }
     }
@@ -157,7 +433,7 @@ mod tests {
         config::setup_test_logging();
         let result = create_command("clock-out::2021-12-20T20:36:23.44Z::this is the clock out test");
         match result{
-            Ok(Command{task, command:_, cmd_datetime: _}) => assert_eq!(task.to_string(), "this is the clock out test"),
+            Ok(Command{task, command:_, cmd_datetime: _, ..}) => assert_eq!(task.to_string(), "this is the clock out test"),
             Err(why) => {
                 println!("We have FAILED: {}", why);
                 assert_eq!(false, true);//let it end
@@ -165,15 +441,45 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_create_pause_and_resume(){
+        let pause = create_command("pause::2021-12-20T12:00:00Z::lunch test").unwrap();
+        assert_eq!(pause.command, CommandType::Pause);
+        assert_eq!(pause.task, "lunch test");
+        let resume = create_command("resume::2021-12-20T13:00:00Z::lunch test").unwrap();
+        assert_eq!(resume.command, CommandType::Resume);
+        //and they survive the round trip through storage
+        assert_eq!("pause".parse::<CommandType>().unwrap(), CommandType::Pause);
+        assert_eq!(CommandType::Resume.to_string().parse::<CommandType>().unwrap(), CommandType::Resume);
+    }
+
+    #[test]
+    fn test_malformed_and_bad_time(){
+        let malformed = create_command("clock-in::2021-12-20T14:00:00Z").err().unwrap();
+        assert!(matches!(malformed, ClockError::MalformedCommand { column: 31, .. }));
+        let bad_time = create_command("clock-in::half past::some task").err().unwrap();
+        assert!(matches!(bad_time, ClockError::BadTimestamp { ref input, .. } if input == "half past"));
+        assert!(bad_time.is_bad_command());
+    }
+
+    #[test]
+    fn test_create_switch(){
+        let switch = create_command("switch::2021-12-20T14:00:00Z::meeting test").unwrap();
+        assert_eq!(switch.command, CommandType::Switch);
+        assert_eq!(switch.task, "meeting test");
+    }
+
     #[test]
     fn test_hash(){
         let result = create_command("clock-out::2021-12-20T20:36:23.44Z::this is the clock out test");
         match result{
             Ok(cmd) => {
-                let mut hasher  = DefaultHasher::new();
-                cmd.hash(&mut hasher);
-                println!("{}", hasher.finish());
-                assert_eq!(10252799804255487277, hasher.finish());
+                let hash = task_hash(&cmd.task);
+                println!("{}", hash);
+                assert_eq!(6846043582203696726, hash);
+                //known FNV-1a test vectors
+                assert_eq!(0xcbf29ce484222325_u64 as i64, task_hash(""));
+                assert_eq!(0xaf63dc4c8601ec8c_u64 as i64, task_hash("a"));
             }
             Err(why) => {
                 panic!("We have FAILED: {}", why);
@@ -181,4 +487,145 @@ mod tests {
         }
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_relative_times() {
+        let now: DateTime<Utc> = "2022-01-31T12:00:00Z".parse().unwrap();
+        let at = |time_str: &str| parse_time(time_str, &now).unwrap().to_rfc3339();
+        assert_eq!(at("2022-01-30T08:00:00+02:00"), "2022-01-30T06:00:00+00:00");
+        assert_eq!(at("now"), "2022-01-31T12:00:00+00:00");
+        assert_eq!(at("-15m"), "2022-01-31T11:45:00+00:00");
+        assert_eq!(at("-1h30m"), "2022-01-31T10:30:00+00:00");
+        assert_eq!(at("+1d10s"), "2022-02-01T12:00:10+00:00");
+        assert_eq!(at("09:30"), "2022-01-31T09:30:00+00:00");
+        assert_eq!(at("today 13:05:30"), "2022-01-31T13:05:30+00:00");
+        assert_eq!(at("yesterday 17:00"), "2022-01-30T17:00:00+00:00");
+        for bad in ["-15", "-15x", "-", "25:00", "tomorrow 09:00", "half past"] {
+            assert!(matches!(parse_time(bad, &now), Err(ClockError::BadTimestamp { .. })), "accepted '{}'", bad);
+        }
+        let cmd = create_command_at("clock-in::-1h::relative test", &now).unwrap();
+        assert_eq!(cmd.cmd_datetime, now - Duration::hours(1));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration(" 1h30m"), Some(Duration::minutes(90)));
+        assert_eq!(parse_duration("-1h"), None);
+    }
+
+    #[test]
+    fn test_overflowing_offsets() {
+        let now = Utc.with_ymd_and_hms(2022, 1, 31, 12, 0, 0).unwrap();
+        //too large for a Duration, too large to add up, and too far from now for a DateTime
+        for bad in ["-99999999999999999999d", "+9999999999999h", "+106751991167d1d", "-99999999999d", "+99999999d"] {
+            assert!(matches!(parse_time(bad, &now), Err(ClockError::BadTimestamp { .. })), "accepted '{}'", bad);
+        }
+        assert_eq!(parse_duration("9999999999999h"), None);
+    }
+
+    #[test]
+    fn test_parse_times_across_dst() {
+        use chrono_tz::Europe::London;
+        //clocks went forward at 01:00 GMT on 2022-03-27, and back at 01:00 GMT on 2022-10-30
+        let spring = London.with_ymd_and_hms(2022, 3, 27, 12, 0, 0).unwrap();
+        let at = |time_str: &str, now| parse_time(time_str, now).unwrap().to_rfc3339();
+        assert_eq!(at("09:30", &spring), "2022-03-27T08:30:00+00:00");
+        assert_eq!(at("yesterday 17:00", &spring), "2022-03-26T17:00:00+00:00");
+        assert_eq!(at("00:30", &spring), "2022-03-27T00:30:00+00:00");
+        //01:30 never happened that night
+        assert!(matches!(parse_time("01:30", &spring), Err(ClockError::BadTimestamp { .. })));
+        //offsets are real time, not wall-clock
+        let after_change = London.with_ymd_and_hms(2022, 3, 27, 2, 30, 0).unwrap();
+        assert_eq!(at("-1h", &after_change), "2022-03-27T00:30:00+00:00");
+
+        //01:30 happened twice, we take the first, still in summer time
+        let autumn = London.with_ymd_and_hms(2022, 10, 30, 12, 0, 0).unwrap();
+        assert_eq!(at("01:30", &autumn), "2022-10-30T00:30:00+00:00");
+        assert_eq!(at("09:30", &autumn), "2022-10-30T09:30:00+00:00");
+        assert_eq!(at("yesterday 09:30", &autumn), "2022-10-29T08:30:00+00:00");
+    }
+
+    #[test]
+    fn test_quoting_and_escaping() {
+        let quoted = create_command("clock-in::2021-12-20T14:00:00Z::'task description'").unwrap();
+        assert_eq!(quoted.task, "task description");
+        let delimiters = create_command("clock-in::2021-12-20T14:00:00Z::\"fix std::fs bug\"").unwrap();
+        assert_eq!(delimiters.task, "fix std::fs bug");
+        let escaped = create_command(r"clock-in::2021-12-20T14:00:00Z::fix std\::fs bug").unwrap();
+        assert_eq!(escaped.task, "fix std::fs bug");
+        let inner_quotes = create_command(r"clock-in::2021-12-20T14:00:00Z::'it\'s a back\\slash'").unwrap();
+        assert_eq!(inner_quotes.task, r"it's a back\slash");
+        //quotes only count at the start
+        let bare = create_command("clock-in::2021-12-20T14:00:00Z::don't quote me").unwrap();
+        assert_eq!(bare.task, "don't quote me");
+    }
+
+    #[test]
+    fn test_tags_and_note() {
+        let cmd = create_command("clock-out::2021-12-20T14:00:00Z::docs::+writing::+'client work'::+writing::note=first draft, mostly").unwrap();
+        assert_eq!(cmd.task, "docs");
+        assert_eq!(cmd.tags, vec!["writing".to_string(), "client work".to_string()]);
+        assert_eq!(cmd.note.as_deref(), Some("first draft, mostly"));
+    }
+
+    #[test]
+    fn test_create_note() {
+        let note = create_command("note::2021-12-20T14:00:00Z::docs::note=found the bug").unwrap();
+        assert_eq!(note.command, CommandType::Note);
+        assert_eq!(note.note.as_deref(), Some("found the bug"));
+        assert!(matches!(create_command("note::2021-12-20T14:00:00Z::docs"), Err(ClockError::MalformedCommand { .. })));
+    }
+
+    #[test]
+    fn test_error_positions() {
+        let column = |check_str: &str| match create_command(check_str) {
+            Err(ClockError::MalformedCommand { column, .. }) => column,
+            other => panic!("Expected a malformed command for '{}', got {:?}", check_str, other),
+        };
+        assert_eq!(column("clock-in"), 9);
+        assert_eq!(column("clock-in::2021-12-20T14:00:00Z::"), 33);
+        assert_eq!(column("clock-in::2021-12-20T14:00:00Z::'never closed"), 46);
+        assert_eq!(column("clock-in::2021-12-20T14:00:00Z::'closed' too soon"), 41);
+        assert_eq!(column("clock-in::2021-12-20T14:00:00Z::task::-tag"), 39);
+        assert_eq!(column("clock-in::2021-12-20T14:00:00Z::task::note=a::note=b"), 47);
+        let err = create_command("clock-in::2021-12-20T14:00:00Z::task::-tag").unwrap_err();
+        assert!(err.to_string().ends_with("expected +tag or note=text at column 39)"));
+    }
+
+    #[test]
+    fn test_command_string_round_trip() {
+        let mut cmds = vec![
+            create_command("clock-in::2021-10-31T04:10:29.316132167Z::'task description'").unwrap(),
+            create_command("pause::2021-10-31T05:00:00+02:00::fix std\\::fs bug::+rust::note=it's 'quoted'\\\\").unwrap(),
+        ];
+        let mut odd = Command::new(CommandType::Switch, "2021-10-31T04:10:29Z".parse().unwrap(), " '::\\\" ".to_string());
+        odd.tags.push("+::".to_string());
+        odd.note = Some(String::new());
+        cmds.push(odd);
+        let mut system_out = Command::new(CommandType::ClockOut, "2021-10-31T04:10:29Z".parse().unwrap(), "stale".to_string());
+        system_out.system = true;
+        cmds.push(system_out);
+        for cmd in cmds {
+            let cmd_str = cmd.to_command_string();
+            assert_eq!(create_command(&cmd_str).unwrap(), cmd, "{} did not round trip", cmd_str);
+        }
+        let cmd = create_command("clock-in::2021-10-31T04:10:29.316132167Z::'task description'").unwrap();
+        assert_eq!(cmd.to_command_string(), COMMAND_EG);
+        assert!(create_command("clock-out::2021-10-31T04:10:29Z::stale::systems").is_err());
+    }
+
+    #[test]
+    fn test_stored_command_string_round_trip() {
+        let cr = ClockRuster::in_memory();
+        let mut cmd = create_command("clock-in::2021-10-31T04:10:29Z::'task description'::+docs::note='first draft'").unwrap();
+        cr.run_clock_command(&cmd).unwrap();
+        let stored = cr.command_list(None, None, None).unwrap().pop().unwrap();
+        assert!(stored.id.is_some());
+
+        let parsed = create_command(&stored.to_command_string()).unwrap();
+        assert_eq!(parsed.id, None);
+        cmd.id = stored.id;
+        assert_eq!(Command { id: stored.id, ..parsed }, cmd);
+    }
+}
+
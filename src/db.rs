@@ -1,28 +1,63 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
-use chrono::{DateTime, Utc};
-use color_eyre::{Report, eyre::eyre};
-use rusqlite::{Connection, params};
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::HashMap;
+use chrono::{DateTime, Duration, Local, Utc};
+use rusqlite::{Connection, OptionalExtension, params, ToSql};
+use rusqlite::types::Type;
 use tracing::info;
-// use std::str::FromStr;
 
 
-use crate::command::{Command, CommandType};
+use crate::journal::{Change, EntryKind, JournalEntry, UndoStack};
+use crate::command::{task_hash, Command, CommandType};
+use crate::error::ClockError;
+use crate::import::{self, ImportReport};
+use crate::integrity::{self, IntegrityReport};
+use crate::migration;
+use crate::policy::{StalePolicy, TrackingPolicy};
+use crate::project::{self, Project};
+use crate::session::{Anomaly, Session, SessionList, TaskState, TaskStatus};
+use crate::store::{ClockStore, CommandFilter, MemoryStore};
 
-pub struct ClockRuster {
-    connection_string: String,
+///Id of the task's project, adding it and every project above it that isn't there yet
+/// None for a task with no path at all, such as an empty one
+pub(crate) fn ensure_project(conn: &Connection, task: &str) -> Result<Option<i64>, ClockError> {
+    let mut insert = conn.prepare_cached("INSERT OR IGNORE into clock_rust_projects (path, parent_id) VALUES (?, ?)")?;
+    let mut select = conn.prepare_cached("select id from clock_rust_projects where path = ?")?;
+    let mut parent_id: Option<i64> = None;
+    for project in project::ancestors(task) {
+        insert.execute(params![project.path, parent_id])?;
+        parent_id = Some(select.query_row([&project.path], |row| row.get(0))?);
+    }
+    Ok(parent_id)
 }
 
-impl ClockRuster {
-    pub fn new() -> Self {
-        Self {
-            connection_string: String::from("./.clockrust"),
-        }
+///Commands with their tags, one row per tag, which SqliteStore::query folds back into one command per id
+const SELECT_COMMANDS: &str = "select command, task, cmd_date, note, id, tag, system from clock_rust_tasks
+                        LEFT JOIN clock_rust_tags ON command_id = id ";
+
+fn write_tags(conn: &Connection, id: i64, tags: &[String]) -> Result<(), ClockError> {
+    let mut stmt = conn.prepare_cached("INSERT OR IGNORE into clock_rust_tags (command_id, tag) VALUES (?, ?)")?;
+    for tag in tags {
+        stmt.execute(params![id, tag])?;
     }
+    Ok(())
+}
 
-    pub fn init(conn_str: &str) -> Self {
+///Keeps commands in a sqlite file
+pub struct SqliteStore {
+    connection_string: String,
+    ///Opened on first use, then kept for as long as we are
+    conn: OnceCell<Connection>,
+    ///Whether conn has been brought up to date yet
+    migrated: Cell<bool>,
+}
+
+impl SqliteStore {
+    ///Nothing is opened until the store is first used
+    pub fn open(conn_str: &str) -> Self {
         Self {
-            connection_string: String::from(conn_str)
+            connection_string: String::from(conn_str),
+            conn: OnceCell::new(),
+            migrated: Cell::new(false),
         }
     }
 
@@ -30,92 +65,458 @@ impl ClockRuster {
         self.connection_string.clone()
     }
 
-    pub fn ensure_storage_exists(&self, conn: &Connection) -> Result<(), Report> {
-        //check for table's existence
-        conn.execute("
-            CREATE TABLE IF NOT EXISTS clock_rust_tasks(
-                id INTEGER PRIMARY KEY ASC,
-                command TEXT,
-                task TEXT,
-                hash INTEGER,
-                cmd_date DATETIME
-            )
-        ", [])?;
-        Ok(())
-        //and create it if it does not exist
+    ///Our connection, opening it and bringing the storage up to date the first time
+    pub fn conn(&self) -> Result<&Connection, ClockError> {
+        let conn = self.kept_conn()?;
+        if !self.migrated.get() {
+            let applied = migration::migrate(conn, false)?;
+            if !applied.is_empty() {
+                info!("Applied migrations {:?}", applied);
+            }
+            self.migrated.set(true);
+        }
+        Ok(conn)
+    }
+
+    ///Migrate the storage on our connection, returning the schema versions applied, see migration::migrate
+    /// A dry run leaves the connection to be brought up to date on first use, as ever
+    pub fn migrate(&self, dry_run: bool) -> Result<Vec<i64>, ClockError> {
+        let applied = migration::migrate(self.kept_conn()?, dry_run)?;
+        if !dry_run {
+            self.migrated.set(true);
+        }
+        Ok(applied)
     }
 
-    pub fn run_clock_command(&self, cmd: &Command) -> Result<(), Report> {
+    ///Our connection, opened but not necessarily up to date
+    fn kept_conn(&self) -> Result<&Connection, ClockError> {
+        if let Some(conn) = self.conn.get() {
+            return Ok(conn);
+        }
         let conn = Connection::open(&self.connection_string)?;
-        match self.ensure_storage_exists(&conn){
-            Ok(_) => {
-                let mut hasher = DefaultHasher::new();
-                cmd.hash(&mut hasher);
-                let updated = conn.execute(r"INSERT into clock_rust_tasks (command, task, hash, cmd_date)
-                                    VALUES (?, ?, ?, ?);",
-                             params![ cmd.command.to_string(), cmd.task, hasher.finish() as i64, cmd.cmd_datetime  ])?;
-                info!("Number of rows inserted {}", updated);
+        Ok(self.conn.get_or_init(|| conn))
+    }
+
+    ///Insert the command under id, or under a new id if there isn't one, returning the id
+    fn write_command(&self, cmd: &Command, restoring: Option<i64>) -> Result<i64, ClockError> {
+        self.atomically(|| {
+            let conn = self.conn()?;
+            if let Some(id) = restoring {
+                check_sealed(conn, id)?;
+            }
+            let project_id = ensure_project(conn, &cmd.task)?;
+            let mut stmt = conn.prepare_cached(r"INSERT into clock_rust_tasks (id, command, task, hash, cmd_date, note, project_id, system)
+                            VALUES (?, ?, ?, ?, ?, ?, ?, ?);")?;
+            let updated = stmt.execute(params![ restoring, cmd.command.to_string(), cmd.task, task_hash(&cmd.task), cmd.cmd_datetime, cmd.note, project_id, cmd.system  ])?;
+            info!("Number of rows inserted {}", updated);
+            let id = conn.last_insert_rowid();
+            write_tags(conn, id, &cmd.tags)?;
+            reseal(conn, id)?;
+            match restoring {
+                //put back among the commands around it, it can change how any of them pair up
+                Some(_) => rebuild_open_sessions(conn, SELECT_COMMANDS)?,
+                None => advance_open_sessions(conn, cmd, id)?,
+            }
+            Ok(id)
+        })
+    }
+
+    ///Run a query built on SELECT_COMMANDS, see query_commands
+    fn query(&self, sql: &str, args: &[&dyn ToSql]) -> Result<Vec<Command>, ClockError> {
+        query_commands(self.conn()?, sql, args)
+    }
+}
+
+///Run a query built on SELECT_COMMANDS, folding its rows back into commands
+fn query_commands(conn: &Connection, sql: &str, args: &[&dyn ToSql]) -> Result<Vec<Command>, ClockError> {
+    let mut stmt = conn.prepare_cached(sql)?;
+    let cmds_iter = stmt
+        .query_map(rusqlite::params_from_iter(args.iter()), |row| {
+            let cs:String = row.get(0)?;
+            let command = cs.parse::<CommandType>()
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, Type::Text, Box::new(e)))?;
+            let task = row.get(1)?;
+            let cmd_datetime:DateTime<Utc> = row.get(2)?;
+            let mut cmd = Command::new(command, cmd_datetime, task);
+            cmd.note = row.get(3)?;
+            let id: i64 = row.get(4)?;
+            cmd.id = Some(id);
+            let tag: Option<String> = row.get(5)?;
+            cmd.tags.extend(tag);
+            cmd.system = row.get(6)?;
+           Ok((id, cmd))
+        })?;
+
+    let mut cmds: Vec<Command> = Vec::new();
+    let mut last_id = None;
+    for res in cmds_iter {
+       let (id, cmd) = res.map_err(|e| match e {
+           rusqlite::Error::FromSqlConversionFailure(_, _, why) => ClockError::CorruptData(why.to_string()),
+           e => ClockError::Storage(e),
+       })?;
+       match cmds.last_mut() {
+           Some(last) if last_id == Some(id) => last.tags.extend(cmd.tags),
+           _ => cmds.push(cmd),
+       }
+       last_id = Some(id);
+    }
+
+    Ok(cmds)
+}
+
+///Link every command from id on to the one before it again, see integrity::link
+/// Called whenever a command is stored, changed or removed
+fn reseal(conn: &Connection, id: i64) -> Result<(), ClockError> {
+    reseal_with(conn, SELECT_COMMANDS, id)
+}
+
+///reseal, reading commands with select, which has the columns of SELECT_COMMANDS
+/// Migrations bring their own, as SELECT_COMMANDS may name columns added after them
+pub(crate) fn reseal_with(conn: &Connection, select: &str, id: i64) -> Result<(), ClockError> {
+    let mut previous = previous_link(conn, id)?;
+    let sql = format!("{} WHERE id >= ? ORDER BY id, clock_rust_tags.rowid", select);
+    let mut stmt = conn.prepare_cached("UPDATE clock_rust_tasks SET chain_hash = ? WHERE id = ?")?;
+    for cmd in query_commands(conn, &sql, &[&id])? {
+        previous = integrity::link(&previous, &cmd);
+        stmt.execute(params![previous, cmd.id])?;
+    }
+    Ok(())
+}
+
+///Link of the last command before id, GENESIS if there is none
+fn previous_link(conn: &Connection, id: i64) -> Result<String, ClockError> {
+    Ok(conn.query_row("select chain_hash from clock_rust_tasks where id < ? ORDER BY id DESC LIMIT 1", [id], |row| row.get(0))
+        .optional()?
+        .flatten()
+        .unwrap_or_else(|| integrity::GENESIS.to_string()))
+}
+
+///Fail with ClockError::CorruptData unless every command from id on is still linked as it was stored
+/// Checked before changing one, as resealing would otherwise seal in whatever was changed outside clockrusting
+fn check_sealed(conn: &Connection, id: i64) -> Result<(), ClockError> {
+    let sql = format!("{} WHERE id >= ? ORDER BY id, clock_rust_tags.rowid", SELECT_COMMANDS);
+    let mut stmt = conn.prepare_cached("select id, chain_hash from clock_rust_tasks WHERE id >= ?")?;
+    let links: HashMap<i64, Option<String>> = stmt.query_map([id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    let chain: Vec<(Command, Option<String>)> = query_commands(conn, &sql, &[&id])?.into_iter()
+        .map(|cmd| {
+            let link = cmd.id.and_then(|id| links.get(&id).cloned().flatten());
+            (cmd, link)
+        })
+        .collect();
+    let report = IntegrityReport::verify_from(&previous_link(conn, id)?, &chain);
+    match report.is_intact() {
+        true => Ok(()),
+        false => Err(ClockError::CorruptData(report.to_string())),
+    }
+}
+
+///Where an open session stands in clock_rust_open_sessions, see SessionList::from_commands for what each command does to it
+struct OpenRow {
+    task: String,
+    start: DateTime<Utc>,
+    ///Completed pauses only, a pause still going is measured when the session is read
+    paused_ns: i64,
+    paused_since: Option<DateTime<Utc>>,
+    notes: Vec<String>,
+    tags: Vec<String>,
+}
+
+impl OpenRow {
+    fn new(cmd: &Command) -> Self {
+        let mut row = Self { task: cmd.task.clone(), start: cmd.cmd_datetime, paused_ns: 0, paused_since: None, notes: Vec::new(), tags: Vec::new() };
+        row.annotate(cmd);
+        row
+    }
+
+    fn read(conn: &Connection, hash: i64) -> Result<Option<Self>, ClockError> {
+        let mut stmt = conn.prepare_cached("select task, start, paused_ns, paused_since, notes, tags from clock_rust_open_sessions WHERE hash = ?")?;
+        let row = stmt.query_row([hash], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, DateTime<Utc>>(1)?, row.get::<_, i64>(2)?, row.get::<_, Option<DateTime<Utc>>>(3)?,
+                row.get::<_, String>(4)?, row.get::<_, String>(5)?))
+        }).optional()?;
+        let corrupt = |why: serde_json::Error| ClockError::CorruptData(why.to_string());
+        row.map(|(task, start, paused_ns, paused_since, notes, tags)| Ok(Self {
+            task,
+            start,
+            paused_ns,
+            paused_since,
+            notes: serde_json::from_str(&notes).map_err(corrupt)?,
+            tags: serde_json::from_str(&tags).map_err(corrupt)?,
+        })).transpose()
+    }
+
+    fn write(&self, conn: &Connection) -> Result<(), ClockError> {
+        let mut stmt = conn.prepare_cached(r"INSERT OR REPLACE into clock_rust_open_sessions (hash, task, start, paused_ns, paused_since, notes, tags)
+                            VALUES (?, ?, ?, ?, ?, ?, ?)")?;
+        stmt.execute(params![task_hash(&self.task), self.task, self.start, self.paused_ns, self.paused_since,
+            serde_json::to_string(&self.notes)?, serde_json::to_string(&self.tags)?])?;
+        Ok(())
+    }
+
+    fn annotate(&mut self, cmd: &Command) {
+        self.notes.extend(cmd.note.clone());
+        for tag in &cmd.tags {
+            if !self.tags.contains(tag) {
+                self.tags.push(tag.clone());
+            }
+        }
+    }
+
+    fn resume(&mut self, at: DateTime<Utc>) {
+        if let Some(since) = self.paused_since.take() {
+            self.paused_ns += (at - since).num_nanoseconds().unwrap_or(i64::MAX);
+        }
+    }
+}
+
+///Time of the latest command stored for the task with this hash, other than the one under id
+fn latest_other(conn: &Connection, hash: i64, id: i64) -> Result<Option<DateTime<Utc>>, ClockError> {
+    let mut stmt = conn.prepare_cached("select cmd_date from clock_rust_tasks WHERE hash = ? AND id != ? ORDER BY cmd_date DESC LIMIT 1")?;
+    Ok(stmt.query_row(params![hash, id], |row| row.get(0)).optional()?)
+}
+
+///Bring clock_rust_open_sessions up to date with the command just stored under id, from the rows already there
+/// A command that goes in before others on a task it touches can change how they pair up, that rebuilds instead
+fn advance_open_sessions(conn: &Connection, cmd: &Command, id: i64) -> Result<(), ClockError> {
+    let hash = task_hash(&cmd.task);
+    let mut backdated = latest_other(conn, hash, id)?.is_some_and(|latest| latest > cmd.cmd_datetime);
+    if cmd.command == CommandType::Switch && !backdated {
+        //ties between tasks pair up in task order, so a switch at the same time as another task's command counts too
+        let mut stmt = conn.prepare_cached("select hash from clock_rust_open_sessions WHERE hash != ?")?;
+        let open: Vec<i64> = stmt.query_map([hash], |row| row.get(0))?.collect::<Result<_, _>>()?;
+        for other in open {
+            if latest_other(conn, other, id)?.is_some_and(|latest| latest >= cmd.cmd_datetime) {
+                backdated = true;
+                break;
             }
-            Err(y) => { return Err(eyre!("Failed to run command: {}", y))}
         }
+    }
+    if backdated {
+        return rebuild_open_sessions(conn, SELECT_COMMANDS);
+    }
+
+    let open = OpenRow::read(conn, hash)?;
+    match (&cmd.command, open) {
+        (CommandType::ClockIn, _) => OpenRow::new(cmd).write(conn),
+        (CommandType::ClockOut, _) => {
+            conn.execute("DELETE from clock_rust_open_sessions WHERE hash = ?", [hash])?;
+            Ok(())
+        }
+        (CommandType::Pause, Some(mut row)) if row.paused_since.is_none() => {
+            row.paused_since = Some(cmd.cmd_datetime);
+            row.annotate(cmd);
+            row.write(conn)
+        }
+        (CommandType::Resume, Some(mut row)) if row.paused_since.is_some() => {
+            row.resume(cmd.cmd_datetime);
+            row.annotate(cmd);
+            row.write(conn)
+        }
+        (CommandType::Note, Some(mut row)) => {
+            row.annotate(cmd);
+            row.write(conn)
+        }
+        (CommandType::Switch, open) => {
+            conn.execute("DELETE from clock_rust_open_sessions WHERE hash != ?", [hash])?;
+            match open {
+                Some(mut row) => {
+                    row.resume(cmd.cmd_datetime);
+                    row.annotate(cmd);
+                    row.write(conn)
+                }
+                None => OpenRow::new(cmd).write(conn),
+            }
+        }
+        //nothing open for it to change
+        _ => Ok(()),
+    }
+}
+
+///Fill clock_rust_open_sessions from scratch, pairing up every command read with select
+/// select has the columns of SELECT_COMMANDS, see reseal_with
+pub(crate) fn rebuild_open_sessions(conn: &Connection, select: &str) -> Result<(), ClockError> {
+    let sql = format!("{} ORDER BY task, cmd_date, id, clock_rust_tags.rowid", select);
+    let list = SessionList::from_commands(&query_commands(conn, &sql, &[])?, Utc::now());
+    conn.execute("DELETE from clock_rust_open_sessions", [])?;
+    for session in list.open_sessions() {
+        let ongoing = session.paused_since.map_or(Duration::zero(), |since| list.as_of - since);
+        OpenRow {
+            task: session.task.clone(),
+            start: session.start,
+            paused_ns: (session.paused - ongoing).num_nanoseconds().unwrap_or(i64::MAX),
+            paused_since: session.paused_since,
+            notes: session.notes.clone(),
+            tags: session.tags.clone(),
+        }.write(conn)?;
+    }
+    Ok(())
+}
+
+impl ClockStore for SqliteStore {
+    fn insert(&self, cmd: &Command) -> Result<i64, ClockError> {
+        self.write_command(cmd, None)
+    }
+
+    fn get(&self, id: i64) -> Result<Option<Command>, ClockError> {
+        let sql = format!("{} WHERE id = ? ORDER BY clock_rust_tags.rowid", SELECT_COMMANDS);
+        Ok(self.query(&sql, &[&id])?.pop())
+    }
+
+    fn update(&self, cmd: &Command) -> Result<(), ClockError> {
+        let id = cmd.id.ok_or_else(|| ClockError::CorruptData(format!("no id to update {} with", cmd)))?;
+        self.atomically(|| {
+            let conn = self.conn()?;
+            check_sealed(conn, id)?;
+            let project_id = ensure_project(conn, &cmd.task)?;
+            let mut stmt = conn.prepare_cached(r"UPDATE clock_rust_tasks SET command = ?, task = ?, hash = ?, cmd_date = ?, note = ?, project_id = ?, system = ?
+                            WHERE id = ?")?;
+            let updated = stmt.execute(params![ cmd.command.to_string(), cmd.task, task_hash(&cmd.task), cmd.cmd_datetime, cmd.note, project_id, cmd.system, id ])?;
+            if updated == 0 {
+                return Err(ClockError::NoSuchCommand(id));
+            }
+            conn.execute("DELETE from clock_rust_tags WHERE command_id = ?", [id])?;
+            write_tags(conn, id, &cmd.tags)?;
+            reseal(conn, id)?;
+            rebuild_open_sessions(conn, SELECT_COMMANDS)
+        })
+    }
+
+    fn delete(&self, id: i64) -> Result<(), ClockError> {
+        self.atomically(|| {
+            let conn = self.conn()?;
+            check_sealed(conn, id)?;
+            conn.execute("DELETE from clock_rust_tags WHERE command_id = ?", [id])?;
+            if conn.execute("DELETE from clock_rust_tasks WHERE id = ?", [id])? == 0 {
+                return Err(ClockError::NoSuchCommand(id));
+            }
+            reseal(conn, id)?;
+            rebuild_open_sessions(conn, SELECT_COMMANDS)
+        })
+    }
+
+    fn restore(&self, cmd: &Command) -> Result<(), ClockError> {
+        let id = cmd.id.ok_or_else(|| ClockError::CorruptData(format!("no id to restore {} under", cmd)))?;
+        self.write_command(cmd, Some(id))?;
         Ok(())
     }
 
-    ///Simplistic "are we tracking this task?" method
-    /// We count the number of clock-in commands
-    /// If > 0, count clock-out commands
-    /// iff clock-in count > clock-out count, return true
-    /// Else return false
-    pub fn currently_tracking(&self, task:&str)->Result<bool, Report>{
-        let conn = Connection::open(&self.connection_string)?;
-        self.ensure_storage_exists(&conn)?;
-        let mut hasher = DefaultHasher::new();
-        task.hash(&mut hasher);
-        let hash = hasher.finish() as i64;
-        //get number clock-in commands
-        let cic = self.count_command(CommandType::ClockIn, hash, &conn)?;
-        //get number clock-out commands
-        let coc  = self.count_command(CommandType::ClockOut, hash, &conn)?;
-
-        info!("clock-in count: {}",cic);
-        info!("clock-out count: {}",coc);
-
-        Ok(cic > coc)
-    }
-
-    ///Count the number of times a command (clock-in or clock-out) has been inserted into db
-    pub fn count_command(&self, cmd_type: CommandType, hash: i64, conn:&Connection)->Result<i16, Report> {
-       let mut count_stm = conn.prepare("select count(*) from clock_rust_tasks where command = ?1 and hash = ?2 ")?;
-        let mut rows = count_stm.query([cmd_type.to_string(), hash.to_string()])?;
-        if let Some(i) = rows.next()?{
-            Ok(i.get(0)?)
-        }else{
-            Ok(0)
+    fn append_journal(&self, entry: &JournalEntry) -> Result<i64, ClockError> {
+        self.atomically(|| {
+            let conn = self.conn()?;
+            let mut stmt = conn.prepare_cached(r"INSERT into clock_rust_journal (kind, target, at, description)
+                            VALUES (?, ?, ?, ?)")?;
+            stmt.execute(params![entry.kind.to_string(), entry.target, entry.at, entry.description])?;
+            let entry_id = conn.last_insert_rowid();
+            let mut change_stmt = conn.prepare_cached(r"INSERT into clock_rust_journal_changes (entry_id, action, command_id, before, after)
+                            VALUES (?, ?, ?, ?, ?)")?;
+            for change in &entry.changes {
+                let command_id = change.command_id().ok_or_else(|| ClockError::CorruptData(format!("no id to journal {} with", change)))?;
+                let before = change.before.as_ref().map(serde_json::to_string).transpose()?;
+                let after = change.after.as_ref().map(serde_json::to_string).transpose()?;
+                change_stmt.execute(params![entry_id, change.action.to_string(), command_id, before, after])?;
+            }
+            Ok(entry_id)
+        })
+    }
+
+    fn journal(&self) -> Result<Vec<JournalEntry>, ClockError> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare_cached("select id, kind, target, at, description from clock_rust_journal ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, Option<i64>>(2)?, row.get::<_, DateTime<Utc>>(3)?, row.get::<_, String>(4)?))
+        })?;
+        let mut entries = Vec::new();
+        for row in rows {
+            let (id, kind, target, at, description) = row?;
+            entries.push(JournalEntry { id: Some(id), kind: kind.parse()?, target, at, description, changes: Vec::new() });
+        }
+
+        let mut stmt = conn.prepare_cached("select entry_id, action, before, after from clock_rust_journal_changes ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, Option<String>>(2)?, row.get::<_, Option<String>>(3)?))
+        })?;
+        let corrupt = |why: serde_json::Error| ClockError::CorruptData(why.to_string());
+        for row in rows {
+            let (entry_id, action, before, after) = row?;
+            let change = Change {
+                action: action.parse()?,
+                before: before.as_deref().map(serde_json::from_str).transpose().map_err(corrupt)?,
+                after: after.as_deref().map(serde_json::from_str).transpose().map_err(corrupt)?,
+            };
+            match entries.iter_mut().find(|entry| entry.id == Some(entry_id)) {
+                Some(entry) => entry.changes.push(change),
+                None => return Err(ClockError::CorruptData(format!("journal change for missing entry {}", entry_id))),
+            }
         }
+        Ok(entries)
     }
 
-    ///Return list of commands
-    /// Optionally limited by time
-    /// Optionally limited to a specific task
-    pub fn command_list(&self, opt_start:Option<DateTime<Utc>>, opt_end:Option<DateTime<Utc>>, opt_task:Option<&str>)->Result<Vec<Command>, Report>{
-        let conn = Connection::open(&self.connection_string)?;
-        let mut sql = "select command, task, cmd_date from clock_rust_tasks ".to_string();
-        let mut args = Vec::new();
-        let mut where_inserted = false;
+    ///Read from clock_rust_open_sessions, rather than pairing up every command ever recorded
+    fn open_sessions(&self, now: DateTime<Utc>) -> Result<Vec<Session>, ClockError> {
+        let mut stmt = self.conn()?.prepare_cached("select task, start, paused_ns, paused_since, notes, tags from clock_rust_open_sessions ORDER BY start, task")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, DateTime<Utc>>(1)?, row.get::<_, i64>(2)?, row.get::<_, Option<DateTime<Utc>>>(3)?,
+                row.get::<_, String>(4)?, row.get::<_, String>(5)?))
+        })?;
+        let corrupt = |why: serde_json::Error| ClockError::CorruptData(why.to_string());
+        let mut sessions = Vec::new();
+        for row in rows {
+            let (task, start, paused_ns, paused_since, notes, tags) = row?;
+            let paused = Duration::nanoseconds(paused_ns) + paused_since.map_or(Duration::zero(), |since| now - since);
+            sessions.push(Session {
+                task,
+                start,
+                end: None,
+                duration: now - start - paused,
+                paused,
+                paused_since,
+                notes: serde_json::from_str(&notes).map_err(corrupt)?,
+                tags: serde_json::from_str(&tags).map_err(corrupt)?,
+                stale_since: None,
+                auto_closed: false,
+            });
+        }
+        Ok(sessions)
+    }
 
+    fn chain(&self) -> Result<Vec<(Command, Option<String>)>, ClockError> {
+        let cmds = self.query(&format!("{} ORDER BY id, clock_rust_tags.rowid", SELECT_COMMANDS), &[])?;
+        let mut stmt = self.conn()?.prepare_cached("select id, chain_hash from clock_rust_tasks")?;
+        let links: HashMap<i64, Option<String>> = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(cmds.into_iter()
+            .map(|cmd| {
+                let link = cmd.id.and_then(|id| links.get(&id).cloned().flatten());
+                (cmd, link)
+            })
+            .collect())
+    }
+
+    fn count(&self, cmd_type: &CommandType, task: &str) -> Result<i64, ClockError> {
+        let mut count_stm = self.conn()?.prepare_cached("select count(*) from clock_rust_tasks where command = ?1 and hash = ?2 ")?;
+        Ok(count_stm.query_row(params![cmd_type.to_string(), task_hash(task)], |row| row.get(0))?)
+    }
 
-        //okay let's try this with straight up strings
-        if let Some(start) = opt_start{
+    fn list(&self, filter: &CommandFilter) -> Result<Vec<Command>, ClockError> {
+        let mut sql = SELECT_COMMANDS.to_string();
+        let mut args: Vec<&dyn ToSql> = Vec::new();
+        let mut where_inserted = false;
+        let hash = filter.task.as_deref().map(task_hash);
+        let project = filter.project.as_deref().map(project::normalize).filter(|project| !project.is_empty());
+
+        //dates go in as dates, so they are written the same way as the stored ones
+        if let Some(start) = &filter.start{
             if !where_inserted  {
                 sql += " WHERE ";
                 where_inserted = true;
             }
 
             sql += " cmd_date >= ?";
-            args.push(start.to_string());
+            args.push(start);
         };
 
-        if let Some(end) = opt_end{
+        if let Some(end) = &filter.end{
             if !where_inserted  {
                 sql += " WHERE ";
                 where_inserted = true;
@@ -124,82 +525,560 @@ impl ClockRuster {
             }
 
             sql += " cmd_date <= ?";
-            args.push(end.to_string());
+            args.push(end);
         };
 
-        if let Some(task) = opt_task{
+        if let Some(hash) = &hash{
             if !where_inserted  {
                 sql += " WHERE ";
-                // where_inserted = true;
+                where_inserted = true;
             }else{
                 sql += " AND ";
             }
 
             sql += " hash = ? ";
+            args.push(hash);
+        };
+
+        //instr rather than like, so % and _ in the search are just text
+        if let Some(text) = &filter.note_contains{
+            if !where_inserted  {
+                sql += " WHERE ";
+                where_inserted = true;
+            }else{
+                sql += " AND ";
+            }
+
+            sql += " instr(lower(note), lower(?)) > 0 ";
+            args.push(text);
+        };
+
+        for tag in &filter.tags{
+            if !where_inserted  {
+                sql += " WHERE ";
+                where_inserted = true;
+            }else{
+                sql += " AND ";
+            }
+
+            sql += " id IN (select command_id from clock_rust_tags where tag = ?) ";
+            args.push(tag);
+        }
 
-            let mut hasher = DefaultHasher::new();
-            task.hash(&mut hasher);
-            let hash = hasher.finish() as i64;
-            args.push(hash.to_string());
+        //the project itself or any below it, whole levels at a time
+        if let Some(project) = &project{
+            if !where_inserted  {
+                sql += " WHERE ";
+            }else{
+                sql += " AND ";
+            }
+
+            sql += " project_id IN (select id from clock_rust_projects where path = ? or substr(path, 1, length(?) + 1) = ? || '/') ";
+            args.push(project);
+            args.push(project);
+            args.push(project);
         };
 
-        sql += " ORDER BY task, cmd_date";
+        sql += " ORDER BY task, cmd_date, id, clock_rust_tags.rowid";
         info!("Sql is = '{}' ", sql);
-        let mut stmt = conn.prepare(&sql)?;
-        let cmds_iter = stmt
-            .query_map(rusqlite::params_from_iter(args.iter()), |row| {
-                let cs:String = row.get(0)?;
-                // let command = CommandType::from_str(&cs)?;
-                // let command = match cs.parse(){
-                //     Ok(cmd_type) => cmd_type,
-                //     Err(e) => rusqlite::Error::from(e),
-                // };
-                let command = cs.parse::<CommandType>().unwrap();
-                let task = row.get(1)?;
-                let cmd_datetime:DateTime<Utc> = row.get(2)?;
-               Ok(Command{
-                   command,
-                   task,
-                   cmd_datetime,
-               })
-            })?;
-
-        let mut cmds: Vec<Command> = Vec::new();
-        for res in cmds_iter {
-            info!("Pushing command into vec");
-           cmds.push(res.unwrap()) ;
-        }
-
-        Ok(cmds)
-
-    }
-
-    // pub fn write_report(&self, opt_start)
+        self.query(&sql, &args)
+    }
+
+    fn projects(&self) -> Result<Vec<Project>, ClockError> {
+        let mut stmt = self.conn()?.prepare_cached("select p.path, parent.path from clock_rust_projects p
+                            LEFT JOIN clock_rust_projects parent ON p.parent_id = parent.id")?;
+        let mut projects: Vec<Project> = stmt.query_map([], |row| Ok(Project { path: row.get(0)?, parent: row.get(1)? }))?
+            .collect::<Result<_, _>>()?;
+        //sqlite would put `a b` before `a/b`, we want every project followed by the ones below it
+        projects.sort_by(|a, b| project::segments(&a.path).cmp(&project::segments(&b.path)));
+        Ok(projects)
+    }
+
+    ///Runs f inside a savepoint, which nests inside a transaction as well as working on its own
+    fn atomically<T, F: FnOnce() -> Result<T, ClockError>>(&self, f: F) -> Result<T, ClockError> {
+        let conn = self.conn()?;
+        conn.execute_batch("SAVEPOINT clockrust")?;
+        match f() {
+            Ok(t) => {
+                conn.execute_batch("RELEASE clockrust")?;
+                Ok(t)
+            }
+            Err(e) => {
+                conn.execute_batch("ROLLBACK TO clockrust; RELEASE clockrust")?;
+                Err(e)
+            }
+        }
+    }
+}
+
+///Tracks time on tasks, keeping the commands in a ClockStore - a sqlite file unless told otherwise
+pub struct ClockRuster<S: ClockStore = SqliteStore> {
+    store: S,
+    policy: TrackingPolicy,
+    stale: StalePolicy,
+    ///Changes made so far by the operation being journaled, None when there isn't one
+    pending: RefCell<Option<Vec<Change>>>,
+}
+
+impl Default for ClockRuster {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClockRuster {
+    pub fn new() -> Self {
+        Self::init("./.clockrust")
+    }
+
+    pub fn init(conn_str: &str) -> Self {
+        Self::with_store(SqliteStore::open(conn_str))
+    }
+
+    pub fn connection_string(&self) -> String{
+        self.store.connection_string()
+    }
+
+    ///Create the storage or bring an older file up to the current schema
+    pub fn ensure_storage_exists(&self, conn: &Connection) -> Result<(), ClockError> {
+        let applied = migration::migrate(conn, false)?;
+        if !applied.is_empty() {
+            info!("Applied migrations {:?}", applied);
+        }
+        Ok(())
+    }
+
+    ///Migrate the storage file, returning the schema versions applied
+    /// A dry run checks the pending migrations work without keeping any of them
+    pub fn migrate(&self, dry_run: bool) -> Result<Vec<i64>, ClockError> {
+        self.store.migrate(dry_run)
+    }
+}
+
+impl ClockRuster<MemoryStore> {
+    ///Nothing is written anywhere, everything is gone when we are
+    pub fn in_memory() -> Self {
+        Self::with_store(MemoryStore::new())
+    }
+}
+
+impl<S: ClockStore> ClockRuster<S> {
+    pub fn with_store(store: S) -> Self {
+        Self {
+            store,
+            policy: TrackingPolicy::default(),
+            stale: StalePolicy::default(),
+            pending: RefCell::new(None),
+        }
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    ///Check every command against what is already open, see TrackingPolicy
+    pub fn with_policy(mut self, policy: TrackingPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn policy(&self) -> TrackingPolicy {
+        self.policy
+    }
+
+    ///Flag sessions left open too long, see StalePolicy
+    /// The end of the day is taken in the local timezone
+    pub fn with_stale_policy(mut self, stale: StalePolicy) -> Self {
+        self.stale = stale;
+        self
+    }
+
+    pub fn stale_policy(&self) -> StalePolicy {
+        self.stale
+    }
+
+    ///Clock out of every open session gone stale under the StalePolicy, returning the clock-outs
+    /// Each is marked as system and goes in where its session went stale, or at the session's last command if that came later
+    /// Only ever run when asked for, everything else just flags stale sessions
+    pub fn close_stale(&self) -> Result<Vec<Command>, ClockError> {
+        let now = Utc::now();
+        let stale: Vec<(Session, DateTime<Utc>)> = self.store.open_sessions(now)?.into_iter()
+            .filter_map(|session| {
+                let stale_at = self.stale.stale_at(&session, &Local)?;
+                (stale_at <= now).then_some((session, stale_at))
+            })
+            .collect();
+        if stale.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.journaled(|| "close stale sessions".to_string(), || {
+            let mut closed = Vec::new();
+            for (session, stale_at) in stale {
+                //a note or pause after it went stale still belongs in the session
+                let since = CommandFilter::new(Some(stale_at), None, Some(&session.task));
+                let at = self.store.list(&since)?.iter().map(|cmd| cmd.cmd_datetime).fold(stale_at, DateTime::max);
+                let mut clock_out = Command::new(CommandType::ClockOut, at, session.task.clone());
+                clock_out.system = true;
+                info!("Closing stale {}", session);
+                self.insert(&clock_out)?;
+                closed.push(clock_out);
+            }
+            Ok(closed)
+        })
+    }
+
+    ///Group several operations so that either all of them are recorded or none are
+    /// eg `cr.transaction(|tx| { tx.run_clock_command(&lunch)?; tx.run_clock_command(&back) })`
+    /// The whole transaction is a single journal entry, so a single undo takes all of it back
+    pub fn transaction<T>(&self, f: impl FnOnce(&Self) -> Result<T, ClockError>) -> Result<T, ClockError> {
+        self.journaled(|| "transaction".to_string(), || f(self))
+    }
+
+    ///Record the command, once it passes the tracking policy
+    /// A refused command fails with ClockError::InvalidTransition
+    pub fn run_clock_command(&self, cmd: &Command) -> Result<(), ClockError> {
+        //a switch is not stored itself, it becomes the clock-outs and clock-in it stands for
+        if cmd.command == CommandType::Switch {
+            return self.switch_with(cmd);
+        }
+        self.journaled(|| cmd.to_string(), || {
+            if self.policy != TrackingPolicy::Permissive {
+                let open = self.store.open_sessions(cmd.cmd_datetime)?;
+                for auto_closed in self.policy.check(cmd, &open)? {
+                    info!("Auto-closing '{}'", auto_closed.task);
+                    self.insert(&auto_closed)?;
+                }
+            }
+            self.insert(cmd)
+        })
+    }
+
+    ///Clock out of every open task and start on another, all at the same time
+    /// Runs in a single transaction, so either all of it is recorded or none of it
+    /// If the task is already being tracked it carries on, if it is paused it is resumed
+    pub fn switch_to(&self, task: &str, at: DateTime<Utc>) -> Result<(), ClockError> {
+        self.switch_with(&Command::new(CommandType::Switch, at, task.to_string()))
+    }
+
+    ///switch_to the switch command's task at its time, keeping its note and tags
+    /// They go on the clock-in or resume, or on a note if the task is already being tracked
+    pub fn switch_with(&self, switch: &Command) -> Result<(), ClockError> {
+        let (task, at) = (switch.task.as_str(), switch.cmd_datetime);
+        self.journaled(|| format!("switch to '{}'", task), || {
+            let mut task_state = TaskState::Idle;
+            for session in self.store.open_sessions(at)? {
+                if session.task == task {
+                    task_state = if session.is_paused() { TaskState::Paused } else { TaskState::Tracking };
+                    continue;
+                }
+                self.insert(&Command::new(CommandType::ClockOut, at, session.task))?;
+            }
+            let command = match task_state {
+                TaskState::Idle => Some(CommandType::ClockIn),
+                TaskState::Paused => Some(CommandType::Resume),
+                TaskState::Tracking if switch.note.is_some() || !switch.tags.is_empty() => Some(CommandType::Note),
+                TaskState::Tracking => None,
+            };
+            if let Some(command) = command {
+                let mut cmd = Command::new(command, at, task.to_string());
+                cmd.note = switch.note.clone();
+                cmd.tags = switch.tags.clone();
+                self.insert(&cmd)?;
+            }
+            info!("Switched to '{}'", task);
+            Ok(())
+        })
+    }
+
+    ///Sessions on every task that is clocked in, whether paused or not
+    /// Stale ones are flagged, see StalePolicy
+    pub fn open_sessions(&self) -> Result<Vec<Session>, ClockError> {
+        let now = Utc::now();
+        let open = SessionList { sessions: self.store.open_sessions(now)?, as_of: now, ..Default::default() };
+        Ok(open.flag_stale(&self.stale, &Local).sessions)
+    }
+
+    ///Every task that is clocked in, with when its session started and how long it has been worked on
+    /// Read straight from the open sessions the store keeps, so it costs the same however long the history
+    pub fn status(&self) -> Result<Vec<TaskStatus>, ClockError> {
+        Ok(self.open_sessions()?.iter().map(TaskStatus::from).collect())
+    }
+
+    ///Are we tracking this task?
+    /// A paused task is not being tracked, even though it is still open
+    pub fn currently_tracking(&self, task:&str)->Result<bool, ClockError>{
+        Ok(self.task_state(task)? == TaskState::Tracking)
+    }
+
+    ///Where the task stands, from its open session if it has one
+    pub fn task_state(&self, task:&str)->Result<TaskState, ClockError>{
+        let hash = task_hash(task);
+        let state = match self.store.open_sessions(Utc::now())?.iter().find(|session| task_hash(&session.task) == hash) {
+            Some(session) if session.is_paused() => TaskState::Paused,
+            Some(_) => TaskState::Tracking,
+            None => TaskState::Idle,
+        };
+        info!("'{}' is {}", task, state);
+        Ok(state)
+    }
+
+    ///Return list of commands
+    /// Optionally limited by time
+    /// Optionally limited to a specific task
+    pub fn command_list(&self, opt_start:Option<DateTime<Utc>>, opt_end:Option<DateTime<Utc>>, opt_task:Option<&str>)->Result<Vec<Command>, ClockError>{
+        self.commands(&CommandFilter::new(opt_start, opt_end, opt_task))
+    }
+
+    ///Commands passing the filter, for limits command_list doesn't take such as searching notes or tags
+    pub fn commands(&self, filter: &CommandFilter)->Result<Vec<Command>, ClockError>{
+        self.store.list(filter)
+    }
+
+    ///Every project recorded tasks are filed under, parents first
+    pub fn projects(&self) -> Result<Vec<Project>, ClockError> {
+        self.store.projects()
+    }
+
+    ///Add a note to the task's open session
+    pub fn add_note(&self, task: &str, at: DateTime<Utc>, note: &str) -> Result<(), ClockError> {
+        let mut cmd = Command::new(CommandType::Note, at, task.to_string());
+        cmd.note = Some(note.to_string());
+        self.run_clock_command(&cmd)
+    }
+
+    ///The command stored under this id, as listed by command_list
+    pub fn command(&self, id: i64) -> Result<Command, ClockError> {
+        self.store.get(id)?.ok_or(ClockError::NoSuchCommand(id))
+    }
+
+    ///Move a stored command to another time, another task, or both, returning it as it now is
+    /// Refused with ClockError::InvalidEdit if any command would no longer pair up into a session that did before,
+    /// see edit
+    pub fn amend_command(&self, id: i64, new_time: Option<DateTime<Utc>>, new_task: Option<&str>) -> Result<Command, ClockError> {
+        let before = self.command(id)?;
+        let mut after = before.clone();
+        if let Some(time) = new_time {
+            after.cmd_datetime = time;
+        }
+        if let Some(task) = new_task {
+            if task.is_empty() {
+                return Err(ClockError::InvalidEdit { id, reason: "a task can't be empty".to_string() });
+            }
+            after.task = task.to_string();
+        }
+        self.journaled(|| format!("amend #{}", id), || self.edit(id, before, Some(after.clone())))?;
+        Ok(after)
+    }
+
+    ///Forget a stored command, returning what it was
+    /// Refused with ClockError::InvalidEdit in the same way as amend_command
+    pub fn delete_command(&self, id: i64) -> Result<Command, ClockError> {
+        let before = self.command(id)?;
+        self.journaled(|| format!("delete #{}", id), || self.edit(id, before.clone(), None))?;
+        Ok(before)
+    }
+
+    ///Check every command is still linked to the one before it as it was when stored, see integrity::link
+    /// The report has the first command that isn't, if any, which anything changing the storage
+    /// other than through ClockRuster leaves behind
+    pub fn verify_integrity(&self) -> Result<IntegrityReport, ClockError> {
+        let report = IntegrityReport::verify(&self.store.chain()?);
+        info!("{}", report);
+        Ok(report)
+    }
+
+    ///Every operation done through ClockRuster, and every undo and redo of one, oldest first
+    pub fn journal(&self) -> Result<Vec<JournalEntry>, ClockError> {
+        self.store.journal()
+    }
+
+    ///Take back the last n operations still in effect, most recent first, returning the undo entries journaled
+    /// Either all n are undone or, failing with ClockError::HistoryExhausted if there aren't that many, none are
+    pub fn undo(&self, n: usize) -> Result<Vec<JournalEntry>, ClockError> {
+        self.take_back(EntryKind::Undo, n)
+    }
+
+    ///Take back the last n undos, most recent first, returning the redo entries journaled
+    /// Recording anything after an undo means it can no longer be redone
+    pub fn redo(&self, n: usize) -> Result<Vec<JournalEntry>, ClockError> {
+        self.take_back(EntryKind::Redo, n)
+    }
+
+    ///Apply the inverse of the last n entries that can be undone, or redone, journaling an entry of kind for each
+    fn take_back(&self, kind: EntryKind, n: usize) -> Result<Vec<JournalEntry>, ClockError> {
+        self.store.atomically(|| {
+            let journal = self.store.journal()?;
+            let stack = UndoStack::from_journal(&journal);
+            let targets = if kind == EntryKind::Redo { stack.redoable } else { stack.undoable };
+            if targets.len() < n {
+                return Err(ClockError::HistoryExhausted { action: kind, requested: n, available: targets.len() });
+            }
+            let mut taken_back = Vec::new();
+            for target in targets.into_iter().rev().take(n) {
+                let entry = journal.iter().find(|entry| entry.id == Some(target))
+                    .ok_or_else(|| ClockError::CorruptData(format!("no journal entry #{}", target)))?;
+                let changes: Vec<Change> = entry.changes.iter().rev().map(Change::inverse).collect();
+                for change in &changes {
+                    self.apply(change)?;
+                }
+                let mut done = JournalEntry { id: None, kind, target: Some(target), at: Utc::now(), description: entry.description.clone(), changes };
+                done.id = Some(self.store.append_journal(&done)?);
+                info!("{}", done);
+                taken_back.push(done);
+            }
+            Ok(taken_back)
+        })
+    }
+
+    ///Make a journaled change to the store again, keeping the command's id
+    fn apply(&self, change: &Change) -> Result<(), ClockError> {
+        match (&change.before, &change.after) {
+            (None, Some(after)) => self.store.restore(after),
+            (Some(_), Some(after)) => self.store.update(after),
+            (Some(before), None) => self.store.delete(before.id.ok_or(ClockError::CorruptData(format!("no id to delete {} by", before)))?),
+            (None, None) => Err(ClockError::CorruptData(format!("nothing to {}", change.action))),
+        }
+    }
+
+    ///Run f as a single operation, journaling every change it makes to the store as one entry
+    /// Operations run inside another, eg by transaction, become part of that one's entry
+    fn journaled<T>(&self, description: impl FnOnce() -> String, f: impl FnOnce() -> Result<T, ClockError>) -> Result<T, ClockError> {
+        let already = self.pending.borrow().as_ref().map(Vec::len);
+        if let Some(mark) = already {
+            //the store rolls back what a failed operation did, so its changes must go too
+            let result = f();
+            if result.is_err() {
+                if let Some(changes) = self.pending.borrow_mut().as_mut() {
+                    changes.truncate(mark);
+                }
+            }
+            return result;
+        }
+        self.store.atomically(|| {
+            *self.pending.borrow_mut() = Some(Vec::new());
+            let result = f();
+            let changes = self.pending.borrow_mut().take().unwrap_or_default();
+            let result = result?;
+            if !changes.is_empty() {
+                self.store.append_journal(&JournalEntry { id: None, kind: EntryKind::Record, target: None, at: Utc::now(), description: description(), changes })?;
+            }
+            Ok(result)
+        })
+    }
+
+    ///Store the command as part of the operation being journaled
+    fn insert(&self, cmd: &Command) -> Result<(), ClockError> {
+        let mut stored = cmd.clone();
+        stored.id = Some(self.store.insert(cmd)?);
+        self.record(Change::insert(stored));
+        Ok(())
+    }
+
+    fn record(&self, change: Change) {
+        if let Some(changes) = self.pending.borrow_mut().as_mut() {
+            changes.push(change);
+        }
+    }
+
+    ///Replace the command under id with after, or delete it if there is no after
+    /// Commands that didn't pair up before, eg a forgotten clock-out's clock-in, are left to be fixed,
+    /// but the change can't leave any other command unpaired
+    fn edit(&self, id: i64, before: Command, after: Option<Command>) -> Result<(), ClockError> {
+        self.store.atomically(|| {
+            let now = Utc::now();
+            let unpaired = |anomalies: &[Anomaly]| anomalies.iter()
+                .filter_map(|anomaly| anomaly.command().id)
+                .collect::<Vec<i64>>();
+            let already = unpaired(&self.store.sessions(&CommandFilter::default(), now)?.anomalies);
+            match &after {
+                Some(after) => self.store.update(after)?,
+                None => self.store.delete(id)?,
+            }
+            let anomalies = self.store.sessions(&CommandFilter::default(), now)?.anomalies;
+            if let Some(anomaly) = anomalies.iter().find(|anomaly| anomaly.command().id.is_some_and(|unpaired| !already.contains(&unpaired))) {
+                return Err(ClockError::InvalidEdit { id, reason: format!("it would leave a {}", anomaly) });
+            }
+            self.record(match after {
+                Some(after) => Change::amend(before, after),
+                None => Change::delete(before),
+            });
+            Ok(())
+        })
+    }
+
+    ///Record commands from elsewhere, oldest first, through run_clock_command
+    /// A command already stored, with the same type, task, time, note and tags, is counted as a duplicate and left alone,
+    /// so importing the same file twice changes nothing, see import::is_duplicate
+    /// Commands the tracking policy refuses are skipped and reported, the rest go in a single transaction
+    pub fn import(&self, mut cmds: Vec<Command>) -> Result<ImportReport, ClockError> {
+        cmds.sort_by_key(|cmd| cmd.cmd_datetime);
+        let description = format!("import {} commands", cmds.len());
+        self.journaled(|| description, || self.transaction(|tx| {
+            let mut report = ImportReport::default();
+            for cmd in cmds {
+                let same_time = CommandFilter::new(Some(cmd.cmd_datetime), Some(cmd.cmd_datetime), Some(&cmd.task));
+                if tx.store.list(&same_time)?.iter().any(|stored| import::is_duplicate(stored, &cmd)) {
+                    report.duplicates += 1;
+                    continue;
+                }
+                match tx.run_clock_command(&cmd) {
+                    Ok(()) => report.imported += 1,
+                    Err(ClockError::InvalidTransition(why)) => report.skipped.push(format!("{}: {}", cmd, why)),
+                    Err(e) => return Err(e),
+                }
+            }
+            info!("Import {}", report);
+            Ok(report)
+        }))
+    }
+
+    ///Pair up clock-in and clock-out commands into sessions
+    /// Takes the same optional limits as command_list
+    /// Open sessions are measured up to now
+    pub fn sessions(&self, opt_start:Option<DateTime<Utc>>, opt_end:Option<DateTime<Utc>>, opt_task:Option<&str>)->Result<SessionList, ClockError>{
+        self.sessions_matching(&CommandFilter::new(opt_start, opt_end, opt_task))
+    }
+
+    ///Sessions from the commands passing the filter, for limits sessions doesn't take such as a project
+    /// Filtering on anything that differs between a session's commands, such as tags, can split sessions up
+    /// Stale sessions are flagged, see StalePolicy
+    pub fn sessions_matching(&self, filter: &CommandFilter)->Result<SessionList, ClockError>{
+        Ok(self.store.sessions(filter, Utc::now())?.flag_stale(&self.stale, &Local))
+    }
 }
 
 
 #[cfg(test)]
 pub mod tests{
     use chrono::Utc;
+    use color_eyre::{Report, eyre::eyre};
     use crate::config;
 
     use crate::command::CommandType;
+    use crate::policy::{StalePolicy, TransitionError};
     use super::*;
 
-    pub const TEST_DB_STRING: &str = "./clock_rust_test";
     pub const TEST_TASK: &str = "Test test data";
     pub const TEST_TASK_2: &str = "Test test data of another kind";
 
+    ///A path for a sqlite file, in a temp dir that goes with everything in it when dropped
+    pub fn temp_db() -> (tempfile::TempDir, String) {
+        let dir = tempfile::tempdir().expect("could not create a temp dir for the test db");
+        let db_string = dir.path().join("clock_rust_test").to_str().expect("temp dir path is not UTF-8").to_string();
+        (dir, db_string)
+    }
+
     #[test]
     fn test_create_table()->Result<(), Report>{
         config::setup_test_logging();
-        let cr = ClockRuster::init(TEST_DB_STRING);
-        if let Ok(conn) = Connection::open(cr.connection_string.clone()){
+        let (_dir, db_string) = temp_db();
+        let cr = ClockRuster::init(&db_string);
+        if let Ok(conn) = Connection::open(cr.connection_string()){
             match cr.ensure_storage_exists(&conn){
                 Ok(_) => {info!("Successfully ran ensure_storage_exists")}
                 Err(why) => {panic!("Could not ensure_storage_exists: {}", why)}
             }
-            let fp = std::path::Path::new(TEST_DB_STRING);
+            let fp = std::path::Path::new(&db_string);
             assert!(std::path::Path::exists(fp));
             //SELECT name FROM sqlite_master WHERE type='table' AND name='{table_name}';
             let mut stmt = conn.prepare("SELECT 1 FROM sqlite_master WHERE type='table' AND name='clock_rust_tasks'")?;
@@ -207,8 +1086,6 @@ pub mod tests{
             let table_count = if let Some(row) = rows.next()?{
                  row.get_unwrap(0)
             }else{ 0 };
-            //delete the file
-            std::fs::remove_file(TEST_DB_STRING).expect("could not delete test sqlite db file");
             assert_eq!(table_count, 1)
 
         }else{
@@ -221,16 +1098,13 @@ pub mod tests{
     #[test]
     fn test_run_clock_in_command()->Result<(), Report>{
         config::setup_test_logging();
-        let cr = ClockRuster::init(TEST_DB_STRING);
-        if let Ok(conn) = Connection::open(cr.connection_string.clone()){
-            cr.ensure_storage_exists(&conn)?;
-            let cmd = Command::new(CommandType::ClockIn, Utc::now(), "Test test data".to_string());
-            match cr.run_clock_command(&cmd) {
-                Ok(_)=>println!("Successfully ran clock in command: {} ", cmd),
-                Err(why)=>panic!("Unable to run command: {}", why),
-            }
+        let cr = ClockRuster::in_memory();
+        let cmd = Command::new(CommandType::ClockIn, Utc::now(), "Test test data".to_string());
+        match cr.run_clock_command(&cmd) {
+            Ok(_)=>println!("Successfully ran clock in command: {} ", cmd),
+            Err(why)=>panic!("Unable to run command: {}", why),
         }
-        std::fs::remove_file(TEST_DB_STRING).expect("could not delete test sqlite db file");
+        assert_eq!(cr.store().count(&CommandType::ClockIn, TEST_TASK)?, 1);
         Ok(())
     }
 
@@ -238,110 +1112,538 @@ pub mod tests{
     fn test_curently_tracking()->Result<(), Report>{
         config::setup_test_logging();
 
-        let cr = ClockRuster::init(TEST_DB_STRING);
-        if let Ok(conn) = Connection::open(cr.connection_string.clone()){
-            cr.ensure_storage_exists(&conn)?;
-            let cmd = Command::new(CommandType::ClockIn, Utc::now(), TEST_TASK.to_string());
-            //we clock in, we should now be tracking
-            match cr.run_clock_command(&cmd) {
-                Ok(_)=>{
-                    match cr.currently_tracking(TEST_TASK){
-                        Ok(tracking) => assert!(tracking),
-                        Err(why) => panic!("Unable to perform tracking query: {}", why),
-                    }
-                },
-                Err(why)=>panic!("Unable to run command: {}", why),
-            };
+        let cr = ClockRuster::in_memory();
+        let cmd = Command::new(CommandType::ClockIn, Utc::now(), TEST_TASK.to_string());
+        //we clock in, we should now be tracking
+        match cr.run_clock_command(&cmd) {
+            Ok(_)=>{
+                match cr.currently_tracking(TEST_TASK){
+                    Ok(tracking) => assert!(tracking),
+                    Err(why) => panic!("Unable to perform tracking query: {}", why),
+                }
+            },
+            Err(why)=>panic!("Unable to run command: {}", why),
+        };
 
-            //now we clockout - should no longer be tracking
-            let cmd = Command::new(CommandType::ClockOut, Utc::now(), TEST_TASK.to_string());
-            match cr.run_clock_command(&cmd){
-                Ok(_)=>{
-                    match cr.currently_tracking(TEST_TASK){
-                        Ok(tracking) => assert!(!tracking),
-                        Err(why) => panic!("Unable to perform tracking query: {}", why),
-                    }
-                },
-                Err(why)=> return Err(eyre!(format!("Unable to run command: {}", why))),
-            }
+        //now we clockout - should no longer be tracking
+        let cmd = Command::new(CommandType::ClockOut, Utc::now(), TEST_TASK.to_string());
+        match cr.run_clock_command(&cmd){
+            Ok(_)=>{
+                match cr.currently_tracking(TEST_TASK){
+                    Ok(tracking) => assert!(!tracking),
+                    Err(why) => panic!("Unable to perform tracking query: {}", why),
+                }
+            },
+            Err(why)=> return Err(eyre!(format!("Unable to run command: {}", why))),
         }
-        std::fs::remove_file(TEST_DB_STRING).expect("could not delete test sqlite db file");
         Ok(())
     }
 
     #[test]
     fn test_command_list()->Result<(), Report>{
         config::setup_test_logging();
-        //ensure we don't have some left-over data interfering
-        std::fs::remove_file(TEST_DB_STRING);//don't care if it fails
-        let cr = ClockRuster::init(TEST_DB_STRING);
-        if let Ok(conn) = Connection::open(cr.connection_string.clone()){
-            cr.ensure_storage_exists(&conn)?;
-            //run a clock-in and clock-out command
-            let ci = create_test_cmd( CommandType::ClockIn,TEST_TASK, "2022-01-31 17:00:28.974008356+00:00");
-            let co = create_test_cmd( CommandType::ClockOut,TEST_TASK, "2022-01-31 17:00:28.974008356+00:00");
-            cr.run_clock_command(&ci)?;
-            cr.run_clock_command(&co)?;
-            //let's start by getting all of them
-            let cl = cr.command_list(None, None, None);
-            match cl{
-                Ok(cmds) => assert!(cmds.len()==2),
-               Err(e) => return Err(eyre!(format!("Unable to run command: {}", e))),
-            }
-        }
-
-        std::fs::remove_file(TEST_DB_STRING).expect("could not delete test sqlite db file");
+        let cr = ClockRuster::in_memory();
+        //run a clock-in and clock-out command
+        let ci = create_test_cmd( CommandType::ClockIn,TEST_TASK, "2022-01-31 17:00:28.974008356+00:00");
+        let co = create_test_cmd( CommandType::ClockOut,TEST_TASK, "2022-01-31 17:00:28.974008356+00:00");
+        cr.run_clock_command(&ci)?;
+        cr.run_clock_command(&co)?;
+        //let's start by getting all of them
+        let cl = cr.command_list(None, None, None);
+        match cl{
+            Ok(cmds) => assert!(cmds.len()==2),
+           Err(e) => return Err(eyre!(format!("Unable to run command: {}", e))),
+        }
         Ok(())
     }
+
     #[test]
     fn test_command_list_with_args()->Result<(), Report>{
         config::setup_test_logging();
-        let cr = ClockRuster::init(TEST_DB_STRING);
-        if let Ok(conn) = Connection::open(cr.connection_string.clone()){
-            cr.ensure_storage_exists(&conn)?;
-            //run a clock-in and clock-out command
-            let ci = create_test_cmd( CommandType::ClockIn,TEST_TASK, "2022-01-31 17:00:28.974008356+00:00");
-            let co = create_test_cmd( CommandType::ClockOut,TEST_TASK_2, "2022-02-04 17:00:28.974008356+00:00");
-            cr.run_clock_command(&ci)?;
-            cr.run_clock_command(&co)?;
-            //limit by start date
-            let start_date: chrono::DateTime<Utc> = "2022-02-01 17:00:28.000000000+00:00".parse()?;
-            let cl = cr.command_list(Option::Some(start_date), None, None);
-            match cl{
-                Ok(cmds) => assert!(cmds.len()==1),
-                Err(e) => return Err(eyre!(format!("Unable to run command: {}", e))),
-            }
-
-            //limit by end date
-            let end_date: chrono::DateTime<Utc> = "2022-02-01 17:00:28.000000000+00:00".parse()?;
-            let cl = cr.command_list(None, Option::Some(end_date), None);
-            match cl{
-                Ok(cmds) => assert!(cmds.len()==1),
-                Err(e) => return Err(eyre!(format!("Unable to run command: {}", e))),
-            }
-
-            // by task
-            //
-            let cl = cr.command_list(None, None, Option::Some(TEST_TASK_2));
-            match cl{
-                Ok(cmds) => assert!(cmds.len()==1),
-                Err(e) => return Err(eyre!(format!("Unable to run command: {}", e))),
-            }
-        }
-
-        std::fs::remove_file(TEST_DB_STRING).expect("could not delete test sqlite db file");
+        //sqlite does its own filtering, so this one runs against a file
+        let (_dir, db_string) = temp_db();
+        let cr = ClockRuster::init(&db_string);
+        //run a clock-in and clock-out command
+        let ci = create_test_cmd( CommandType::ClockIn,TEST_TASK, "2022-01-31 17:00:28.974008356+00:00");
+        let co = create_test_cmd( CommandType::ClockOut,TEST_TASK_2, "2022-02-04 17:00:28.974008356+00:00");
+        cr.run_clock_command(&ci)?;
+        cr.run_clock_command(&co)?;
+        //limit by start date
+        let start_date: chrono::DateTime<Utc> = "2022-02-01 17:00:28.000000000+00:00".parse()?;
+        let by_start = cr.command_list(Option::Some(start_date), None, None)?;
+
+        //limit by end date
+        let end_date: chrono::DateTime<Utc> = "2022-02-01 17:00:28.000000000+00:00".parse()?;
+        let by_end = cr.command_list(None, Option::Some(end_date), None)?;
+
+        // by task
+        let by_task = cr.command_list(None, None, Option::Some(TEST_TASK_2))?;
+
+        assert_eq!(by_start.len(), 1);
+        assert_eq!(by_end.len(), 1);
+        assert_eq!(by_task.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sessions()->Result<(), Report>{
+        config::setup_test_logging();
+        let cr = ClockRuster::in_memory();
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 17:00:00+00:00"))?;
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK_2, "2022-01-31 17:30:00+00:00"))?;
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockOut, TEST_TASK, "2022-01-31 18:00:00+00:00"))?;
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockOut, TEST_TASK, "2022-01-31 18:05:00+00:00"))?;
+
+        let list = cr.sessions(None, None, None)?;
+        assert_eq!(list.sessions.len(), 2);
+        assert_eq!(list.sessions[0].task, TEST_TASK);
+        assert_eq!(list.sessions[0].duration, chrono::Duration::hours(1));
+        assert!(list.sessions[1].is_open());
+        assert_eq!(list.anomalies.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate()->Result<(), Report>{
+        config::setup_test_logging();
+        let (_dir, db_string) = temp_db();
+        let cr = ClockRuster::init(&db_string);
+        let dry_run = cr.migrate(true)?;
+        let untouched = migration::schema_version(&Connection::open(&db_string)?)?;
+        let applied = cr.migrate(false)?;
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 17:00:00+00:00"))?;
+        let again = cr.migrate(false)?;
+
+        assert_eq!(dry_run, (1..=migration::latest_version()).collect::<Vec<_>>());
+        assert_eq!(untouched, 0);
+        assert_eq!(applied, dry_run);
+        assert!(again.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rehash_legacy_tasks()->Result<(), Report>{
+        config::setup_test_logging();
+        let (_dir, db_string) = temp_db();
+        {
+            //a file from before stable hashes, with hashes we can no longer reproduce
+            let conn = Connection::open(&db_string)?;
+            conn.execute("CREATE TABLE clock_rust_tasks(id INTEGER PRIMARY KEY ASC, command TEXT, task TEXT, hash INTEGER, cmd_date DATETIME)", [])?;
+            conn.execute("INSERT into clock_rust_tasks (command, task, hash, cmd_date) VALUES ('clock-in', ?1, 1234, '2022-01-31 17:00:00+00:00')", [TEST_TASK])?;
+        }
+        let cr = ClockRuster::init(&db_string);
+        let tracking = cr.currently_tracking(TEST_TASK)?;
+        let cmds = cr.command_list(None, None, Some(TEST_TASK))?;
+        let dry_run = cr.migrate(true)?;
+        assert!(tracking);
+        assert_eq!(cmds.len(), 1);
+        assert!(dry_run.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_paused_is_open_but_not_tracking()->Result<(), Report>{
+        config::setup_test_logging();
+        let cr = ClockRuster::in_memory();
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00"))?;
+        cr.run_clock_command(&create_test_cmd(CommandType::Pause, TEST_TASK, "2022-01-31 12:00:00+00:00"))?;
+        let paused_state = cr.task_state(TEST_TASK)?;
+        let paused_tracking = cr.currently_tracking(TEST_TASK)?;
+        cr.run_clock_command(&create_test_cmd(CommandType::Resume, TEST_TASK, "2022-01-31 12:30:00+00:00"))?;
+        let resumed_tracking = cr.currently_tracking(TEST_TASK)?;
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockOut, TEST_TASK, "2022-01-31 17:00:00+00:00"))?;
+        let list = cr.sessions(None, None, None)?;
+
+        assert_eq!(paused_state, TaskState::Paused);
+        assert!(paused_state.is_open());
+        assert!(!paused_tracking);
+        assert!(resumed_tracking);
+        assert_eq!(list.sessions.len(), 1);
+        assert_eq!(list.sessions[0].duration, chrono::Duration::minutes(7 * 60 + 30));
+        Ok(())
+    }
+
+    #[test]
+    fn test_status()->Result<(), Report>{
+        config::setup_test_logging();
+        let cr = ClockRuster::in_memory();
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00"))?;
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK_2, "2022-01-31 10:00:00+00:00"))?;
+        cr.run_clock_command(&create_test_cmd(CommandType::Pause, TEST_TASK_2, "2022-01-31 11:00:00+00:00"))?;
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, "finished", "2022-01-31 08:00:00+00:00"))?;
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockOut, "finished", "2022-01-31 08:30:00+00:00"))?;
+        let status = cr.status()?;
+        let paused_elapsed = status[1].elapsed;
+        cr.undo(1)?;
+        let after_undo = cr.status()?;
+
+        let states: Vec<(&str, TaskState)> = status.iter().map(|open| (open.task.as_str(), open.state)).collect();
+        assert_eq!(states, vec![(TEST_TASK, TaskState::Tracking), (TEST_TASK_2, TaskState::Paused)]);
+        assert_eq!(status[0].since, "2022-01-31 09:00:00+00:00".parse::<DateTime<Utc>>()?);
+        assert_eq!(paused_elapsed, Duration::hours(1));
+        assert!(!status[0].needs_review);
+        //taking back the clock-out opens the session again
+        assert_eq!(after_undo.len(), 3);
+        assert_eq!(after_undo[0].task, "finished");
+        Ok(())
+    }
+
+    #[test]
+    fn test_switch_to()->Result<(), Report>{
+        config::setup_test_logging();
+        let cr = ClockRuster::in_memory();
+        let third_task = "Test test data, third";
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00"))?;
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK_2, "2022-01-31 09:30:00+00:00"))?;
+        cr.run_clock_command(&create_test_cmd(CommandType::Pause, TEST_TASK_2, "2022-01-31 09:45:00+00:00"))?;
+        let at: DateTime<Utc> = "2022-01-31 10:00:00+00:00".parse()?;
+        cr.switch_to(third_task, at)?;
+        let open: Vec<String> = cr.open_sessions()?.into_iter().map(|s| s.task).collect();
+        //and a switch command does the same
+        cr.run_clock_command(&create_test_cmd(CommandType::Switch, TEST_TASK, "2022-01-31 11:00:00+00:00"))?;
+        let open_after_command: Vec<String> = cr.open_sessions()?.into_iter().map(|s| s.task).collect();
+        let list = cr.sessions(None, None, None)?;
+
+        assert_eq!(open, vec![third_task.to_string()]);
+        assert_eq!(open_after_command, vec![TEST_TASK.to_string()]);
+        assert!(list.anomalies.is_empty());
+        assert_eq!(list.sessions.len(), 4);
+        assert!(list.sessions.iter().filter(|s| !s.is_open()).all(|s| s.end == Some(at) || s.task == third_task));
+        Ok(())
+    }
+
+    #[test]
+    fn test_switch_keeps_note_and_tags()->Result<(), Report>{
+        config::setup_test_logging();
+        let cr = ClockRuster::in_memory();
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00"))?;
+        let mut switch = create_test_cmd(CommandType::Switch, TEST_TASK_2, "2022-01-31 10:00:00+00:00");
+        switch.note = Some("client call".to_string());
+        switch.tags = vec!["billable".to_string()];
+        cr.run_clock_command(&switch)?;
+        //already tracking, so they go on a note
+        let mut again = create_test_cmd(CommandType::Switch, TEST_TASK_2, "2022-01-31 10:30:00+00:00");
+        again.tags = vec!["meeting".to_string()];
+        cr.run_clock_command(&again)?;
+        let open = cr.open_sessions()?;
+
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].task, TEST_TASK_2);
+        assert_eq!(open[0].notes, vec!["client call".to_string()]);
+        assert_eq!(open[0].tags, vec!["billable".to_string(), "meeting".to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_task_policy()->Result<(), Report>{
+        config::setup_test_logging();
+        let strict = ClockRuster::in_memory().with_policy(TrackingPolicy::SingleTask { auto_close: false });
+        strict.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:14:00+00:00"))?;
+        let refused = strict.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK_2, "2022-01-31 10:00:00+00:00"));
+        let not_tracking = strict.run_clock_command(&create_test_cmd(CommandType::ClockOut, TEST_TASK_2, "2022-01-31 10:00:00+00:00"));
+
+        let auto_close = strict.with_policy(TrackingPolicy::SingleTask { auto_close: true });
+        auto_close.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK_2, "2022-01-31 10:00:00+00:00"))?;
+        let open = auto_close.open_sessions()?;
+        let cmds = auto_close.command_list(None, None, None)?;
+
+        assert!(matches!(refused, Err(ClockError::InvalidTransition(TransitionError::AlreadyTracking { ref task, .. })) if task == TEST_TASK));
+        assert!(matches!(not_tracking, Err(ClockError::InvalidTransition(TransitionError::NotTracking { .. }))));
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].task, TEST_TASK_2);
+        assert_eq!(cmds.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_command_type()->Result<(), Report>{
+        config::setup_test_logging();
+        let (_dir, db_string) = temp_db();
+        let cr = ClockRuster::init(&db_string);
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00"))?;
+        Connection::open(&db_string)?.execute("update clock_rust_tasks set command = 'clock-sideways'", [])?;
+        let listed = cr.command_list(None, None, None);
+        assert!(matches!(listed, Err(ClockError::CorruptData(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction()->Result<(), Report>{
+        config::setup_test_logging();
+        let cr = ClockRuster::in_memory().with_policy(TrackingPolicy::MultiTask);
+        //the clock-out is refused, so the clock-in goes too
+        let refused = cr.transaction(|tx| {
+            tx.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00"))?;
+            tx.run_clock_command(&create_test_cmd(CommandType::ClockOut, TEST_TASK_2, "2022-01-31 10:00:00+00:00"))
+        });
+        let after_refused = cr.command_list(None, None, None)?.len();
+        cr.transaction(|tx| {
+            for hour in 9..17 {
+                tx.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK, &format!("2022-01-31 {:02}:00:00+00:00", hour)))?;
+                tx.run_clock_command(&create_test_cmd(CommandType::ClockOut, TEST_TASK, &format!("2022-01-31 {:02}:30:00+00:00", hour)))?;
+            }
+            Ok(())
+        })?;
+        let list = cr.sessions(None, None, None)?;
+
+        assert!(matches!(refused, Err(ClockError::InvalidTransition(_))));
+        assert_eq!(after_refused, 0);
+        assert_eq!(list.sessions.len(), 8);
+        Ok(())
+    }
+
+    #[test]
+    fn test_import()->Result<(), Report>{
+        config::setup_test_logging();
+        let cr = ClockRuster::in_memory().with_policy(TrackingPolicy::MultiTask);
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00"))?;
+        let cmds = vec![
+            create_test_cmd(CommandType::ClockOut, TEST_TASK, "2022-01-31 10:00:00+00:00"),
+            create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00"),
+            //refused, we are not tracking the second task
+            create_test_cmd(CommandType::ClockOut, TEST_TASK_2, "2022-01-31 10:00:00+00:00"),
+        ];
+        let first = cr.import(cmds.clone())?;
+        let again = cr.import(cmds)?;
+        //tagged since, so not the stored clock-out over again
+        let mut tagged = create_test_cmd(CommandType::ClockOut, TEST_TASK, "2022-01-31 10:00:00+00:00");
+        tagged.tags = vec!["billable".to_string()];
+        let retagged = cr.import(vec![tagged])?;
+        let stored = cr.command_list(None, None, None)?;
+
+        assert_eq!((first.imported, first.duplicates, first.skipped.len()), (1, 1, 1));
+        assert_eq!((again.imported, again.duplicates, again.skipped.len()), (0, 2, 1));
+        assert_eq!((retagged.imported, retagged.duplicates, retagged.skipped.len()), (0, 0, 1));
+        assert_eq!(stored.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_notes()->Result<(), Report>{
+        config::setup_test_logging();
+        let cr = ClockRuster::in_memory().with_policy(TrackingPolicy::MultiTask);
+        let mut clock_in = create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00");
+        clock_in.note = Some("starting on the parser".to_string());
+        cr.run_clock_command(&clock_in)?;
+        cr.add_note(TEST_TASK, "2022-01-31 10:00:00+00:00".parse()?, "found the escaping bug")?;
+        let not_open = cr.add_note(TEST_TASK_2, "2022-01-31 10:00:00+00:00".parse()?, "never clocked in");
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockOut, TEST_TASK, "2022-01-31 11:00:00+00:00"))?;
+        let found = cr.commands(&CommandFilter::default().with_note_search("BUG"))?;
+        let list = cr.sessions(None, None, None)?;
+
+        assert!(matches!(not_open, Err(ClockError::InvalidTransition(TransitionError::NotTracking { .. }))));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].command, CommandType::Note);
+        assert_eq!(list.sessions.len(), 1);
+        assert_eq!(list.sessions[0].notes, vec!["starting on the parser".to_string(), "found the escaping bug".to_string()]);
+        assert_eq!(list.sessions[0].duration, chrono::Duration::hours(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_amend_and_delete()->Result<(), Report>{
+        config::setup_test_logging();
+        let cr = ClockRuster::in_memory();
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00"))?;
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockOut, TEST_TASK, "2022-01-31 17:00:00+00:00"))?;
+        //forgot to clock out of this one
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK_2, "2022-01-31 10:00:00+00:00"))?;
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK_2, "2022-02-01 09:00:00+00:00"))?;
+        let cmds = cr.command_list(None, None, None)?;
+        let ids: Vec<i64> = cmds.iter().map(|cmd| cmd.id.unwrap()).collect();
+        assert_eq!(cr.command(ids[1])?, cmds[1]);
+
+        //clocking out before clocking in, or deleting a clock-in with its clock-out still there, is refused
+        let early = cr.amend_command(ids[1], Some("2022-01-31 08:00:00+00:00".parse()?), None);
+        assert!(matches!(early, Err(ClockError::InvalidEdit { id, .. }) if id == ids[1]));
+        assert!(matches!(cr.delete_command(ids[0]), Err(ClockError::InvalidEdit { .. })));
+        assert!(matches!(cr.amend_command(ids[0], None, Some("")), Err(ClockError::InvalidEdit { .. })));
+        assert!(matches!(cr.delete_command(99), Err(ClockError::NoSuchCommand(99))));
+        assert_eq!(cr.command_list(None, None, None)?, cmds);
+
+        //the forgotten clock-in was already unpaired, so it can be fixed
+        let fixed = cr.amend_command(ids[2], Some("2022-01-31 16:00:00+00:00".parse()?), None)?;
+        assert_eq!(fixed.id, Some(ids[2]));
+        let moved = cr.amend_command(ids[1], Some("2022-01-31 12:00:00+00:00".parse()?), Some("lunch"));
+        assert!(moved.is_err(), "moving the clock-out to another task orphans it");
+        let deleted = cr.delete_command(ids[2])?;
+        assert_eq!(deleted, fixed);
+
+        let list = cr.sessions(None, None, None)?;
+        assert!(list.anomalies.is_empty());
+        assert_eq!(list.sessions.len(), 2);
+
+        //four clock commands, then the amend and delete, refused edits leave nothing behind
+        let journal = cr.journal()?;
+        assert_eq!(journal.len(), 6);
+        assert_eq!(journal[4].description, format!("amend #{}", ids[2]));
+        assert_eq!(journal[4].changes, vec![Change::amend(cmds[2].clone(), fixed.clone())]);
+        assert_eq!(journal[5].changes, vec![Change::delete(fixed)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_journal_stored()->Result<(), Report>{
+        config::setup_test_logging();
+        let (_dir, db_string) = temp_db();
+        let cr = ClockRuster::init(&db_string);
+        let mut clock_in = create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00");
+        clock_in.tags = vec!["billable".to_string()];
+        cr.run_clock_command(&clock_in)?;
+        let id = cr.command_list(None, None, None)?[0].id.unwrap();
+        let amended = cr.amend_command(id, None, Some(TEST_TASK_2))?;
+        let stored = cr.command(id)?;
+        //undone in another process, the clock-in is back on its first task
+        let undone = ClockRuster::init(&db_string).undo(1)?;
+        let restored = cr.command(id)?;
+        cr.delete_command(id)?;
+        cr.undo(1)?;
+        let journal = ClockRuster::init(&db_string).journal()?;
+        let recreated = cr.command(id)?;
+
+        assert_eq!(stored, amended);
+        assert_eq!(stored.tags, vec!["billable".to_string()]);
+        assert_eq!(restored.task, TEST_TASK);
+        assert_eq!(recreated, restored);
+        assert_eq!(journal.len(), 5);
+        assert_eq!(journal[1].changes[0].before.as_ref().map(|cmd| cmd.task.as_str()), Some(TEST_TASK));
+        assert_eq!(journal[1].changes[0].after, Some(amended));
+        assert_eq!(journal[2], undone[0]);
+        assert_eq!(journal[2].target, journal[1].id);
+        assert_eq!(journal[4].kind, EntryKind::Undo);
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_integrity()->Result<(), Report>{
+        config::setup_test_logging();
+        let (_dir, db_string) = temp_db();
+        let cr = ClockRuster::init(&db_string);
+        for dt in ["2022-01-31 09:00:00+00:00", "2022-01-31 10:00:00+00:00", "2022-01-31 11:00:00+00:00", "2022-01-31 12:00:00+00:00"] {
+            cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK, dt))?;
+        }
+        //changes made through ClockRuster reseal the chain
+        cr.amend_command(2, None, Some(TEST_TASK_2))?;
+        cr.delete_command(3)?;
+        cr.undo(1)?;
+        let sealed = cr.verify_integrity()?;
+
+        let conn = Connection::open(&db_string)?;
+        conn.execute("UPDATE clock_rust_tasks SET cmd_date = '2022-01-31 10:30:00+00:00' WHERE id = 2", [])?;
+        let edited = cr.verify_integrity()?;
+        conn.execute("UPDATE clock_rust_tasks SET cmd_date = '2022-01-31 10:00:00+00:00' WHERE id = 2", [])?;
+        let put_back = cr.verify_integrity()?;
+        conn.execute("DELETE FROM clock_rust_tasks WHERE id = 3", [])?;
+        let deleted = cr.verify_integrity()?;
+
+        assert!(sealed.is_intact());
+        assert_eq!(sealed.verified, 4);
+        assert_eq!(edited.verified, 1);
+        assert_eq!(edited.broken.map(|broken| broken.id), Some(2));
+        assert_eq!(put_back, sealed);
+        assert_eq!(deleted.broken.map(|broken| broken.id), Some(4));
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_resealing_over_tampering()->Result<(), Report>{
+        config::setup_test_logging();
+        let (_dir, db_string) = temp_db();
+        let cr = ClockRuster::init(&db_string);
+        for dt in ["2022-01-31 09:00:00+00:00", "2022-01-31 10:00:00+00:00", "2022-01-31 11:00:00+00:00"] {
+            cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK, dt))?;
+        }
+        let conn = Connection::open(&db_string)?;
+        conn.execute("UPDATE clock_rust_tasks SET cmd_date = '2022-01-31 10:30:00+00:00' WHERE id = 3", [])?;
+        let amended = cr.amend_command(1, None, Some(TEST_TASK_2));
+        let deleted = cr.delete_command(2);
+        let report = cr.verify_integrity()?;
+        let unchanged = cr.command(1)?;
+
+        assert!(matches!(amended, Err(ClockError::CorruptData(_))));
+        assert!(matches!(deleted, Err(ClockError::CorruptData(_))));
+        assert_eq!(report.broken.map(|broken| broken.id), Some(3));
+        assert_eq!(unchanged.task, TEST_TASK);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale_sessions()->Result<(), Report>{
+        config::setup_test_logging();
+        let twelve_hours = StalePolicy { max_duration: Some(chrono::Duration::hours(12)), ..Default::default() };
+        let cr = ClockRuster::in_memory().with_stale_policy(twelve_hours);
+        //forgotten overnight, with a note long after it went stale
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00"))?;
+        cr.add_note(TEST_TASK, "2022-01-31 23:00:00+00:00".parse()?, "still here")?;
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK_2, "2022-02-01 09:00:00+00:00"))?;
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockOut, TEST_TASK_2, "2022-02-01 10:00:00+00:00"))?;
+
+        //flagged, but left open
+        assert!(cr.currently_tracking(TEST_TASK)?);
+        let list = cr.sessions(None, None, None)?;
+        assert_eq!(list.needing_review().count(), 1);
+        assert_eq!(cr.open_sessions()?[0].stale_since, Some("2022-01-31 21:00:00+00:00".parse()?));
+
+        //reading is not closing
+        assert!(cr.journal()?.iter().all(|entry| entry.description != "close stale sessions"));
+        assert_eq!(cr.close_stale()?.len(), 1);
+        assert!(!cr.currently_tracking(TEST_TASK)?);
+        assert!(cr.close_stale()?.is_empty(), "already closed");
+        let clock_out = cr.command_list(None, None, Some(TEST_TASK))?.pop().unwrap();
+        assert!(clock_out.system);
+        assert_eq!(clock_out.cmd_datetime, "2022-01-31 23:00:00+00:00".parse::<DateTime<Utc>>()?);
+        let list = cr.sessions(None, None, None)?;
+        assert!(list.anomalies.is_empty());
+        assert!(list.sessions[0].auto_closed);
+        assert_eq!(list.sessions[0].notes, vec!["still here".to_string()]);
+        assert_eq!(list.needing_review().count(), 1);
+        assert_eq!(cr.journal()?.last().map(|entry| entry.description.as_str()), Some("close stale sessions"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo_redo()->Result<(), Report>{
+        config::setup_test_logging();
+        let cr = ClockRuster::in_memory().with_policy(TrackingPolicy::SingleTask { auto_close: true });
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00"))?;
+        //auto-closes the first task, both commands are one operation
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockIn, TEST_TASK_2, "2022-01-31 11:00:00+00:00"))?;
+        let id = cr.command_list(None, None, None)?[0].id.unwrap();
+        cr.amend_command(id, Some("2022-01-31 08:00:00+00:00".parse()?), None)?;
+        let before_undo = cr.command_list(None, None, None)?;
+        assert_eq!(before_undo.len(), 3);
+
+        assert!(matches!(cr.redo(1), Err(ClockError::HistoryExhausted { available: 0, .. })));
+        assert!(matches!(cr.undo(4), Err(ClockError::HistoryExhausted { requested: 4, available: 3, .. })));
+        let undone = cr.undo(2)?;
+        assert_eq!(undone.len(), 2);
+        assert_eq!(undone[0].description, format!("amend #{}", id));
+        let mut first = create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00");
+        first.id = Some(id);
+        assert_eq!(cr.command_list(None, None, None)?, vec![first]);
+
+        cr.redo(2)?;
+        assert_eq!(cr.command_list(None, None, None)?, before_undo);
+
+        //recording something new means what was undone can't be redone
+        cr.undo(1)?;
+        cr.run_clock_command(&create_test_cmd(CommandType::ClockOut, TEST_TASK_2, "2022-01-31 12:00:00+00:00"))?;
+        assert!(matches!(cr.redo(1), Err(ClockError::HistoryExhausted { .. })));
+
+        //an import is a single operation too
+        cr.undo(3)?;
+        assert!(cr.command_list(None, None, None)?.is_empty());
+        cr.import(before_undo.clone())?;
+        assert_eq!(cr.undo(1)?[0].description, "import 3 commands");
+        assert!(cr.command_list(None, None, None)?.is_empty());
+        assert!(cr.undo(1).is_err());
         Ok(())
     }
 
     ///Utility method for creating test commands to log
     pub fn create_test_cmd(command:CommandType, task_str:&str, dt:&str ) -> Command {
         let task = task_str.to_string();
-       Command{
-           command,
-           task,
-           cmd_datetime: dt.parse().unwrap(),
-       }
+       Command::new(command, dt.parse().unwrap(), task)
     }
 
 }
-
@@ -0,0 +1,118 @@
+use std::fmt::{Display, Formatter};
+
+use crate::command::COMMAND_EG;
+use crate::journal::EntryKind;
+use crate::policy::TransitionError;
+
+///Everything that can go wrong in clockrusting
+/// Implements std::error::Error, so `?` still turns it into a color_eyre Report for callers that want one
+#[derive(Debug)]
+pub enum ClockError {
+    ///Command type we don't know, holding what we were given
+    UnknownCommand(String),
+    ///Command string not in COMMAND-TYPE::TIME::DESCRIPTION form, holding what we were given
+    /// and the column, counting characters from 1, where it went wrong
+    MalformedCommand { input: String, column: usize, reason: String },
+    ///Time we could not understand
+    BadTimestamp { input: String, reason: String },
+    ///Entry in an import file we could not understand, line counting from 1
+    BadImport { line: usize, reason: String },
+    ///The tracking policy refused the command
+    InvalidTransition(TransitionError),
+    ///No command is stored under this id
+    NoSuchCommand(i64),
+    ///Amending or deleting the command would leave commands that no longer pair up into sessions
+    InvalidEdit { id: i64, reason: String },
+    ///Asked to undo or redo more operations than there are to take back
+    HistoryExhausted { action: EntryKind, requested: usize, available: usize },
+    ///Storage is at a schema version newer than this clockrusting knows
+    UnsupportedSchema { found: i64, latest: i64 },
+    ///Stored data that makes no sense, eg an unknown command type
+    CorruptData(String),
+    ///Reading or writing the sqlite store failed
+    Storage(rusqlite::Error),
+    ///Writing a report or export failed
+    Io(std::io::Error),
+}
+
+impl Display for ClockError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClockError::UnknownCommand(given) => write!(f, "Fail, available commands: clock-in | clock-out | pause | resume | switch | note, eg {} (got '{}')", COMMAND_EG, given),
+            ClockError::MalformedCommand { input, column, reason } => write!(f, "FAIL, usage command::time::title, eg {} (got '{}': {} at column {})", COMMAND_EG, input, reason, column),
+            ClockError::BadTimestamp { input, reason } => write!(f, "ParseError: {} for '{}'\n FAIL: please supply datetime in rfc3339 format, or now, -15m, 09:30 or yesterday 17:00, eg: {}", reason, input, COMMAND_EG),
+            ClockError::BadImport { line, reason } => write!(f, "Could not import line {}: {}", line, reason),
+            ClockError::InvalidTransition(why) => write!(f, "{}", why),
+            ClockError::NoSuchCommand(id) => write!(f, "No command with id {}", id),
+            ClockError::InvalidEdit { id, reason } => write!(f, "Can't change command {}: {}", id, reason),
+            ClockError::HistoryExhausted { action, requested, available } => write!(f, "Can't {} {} operations, only {} can be", action, requested, available),
+            ClockError::UnsupportedSchema { found, latest } => write!(f, "Storage is at schema version {} but this clockrusting only knows up to {}", found, latest),
+            ClockError::CorruptData(what) => write!(f, "Stored data makes no sense: {}", what),
+            ClockError::Storage(why) => write!(f, "Storage failed: {}", why),
+            ClockError::Io(why) => write!(f, "Could not write: {}", why),
+        }
+    }
+}
+
+impl std::error::Error for ClockError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClockError::InvalidTransition(why) => Some(why),
+            ClockError::Storage(why) => Some(why),
+            ClockError::Io(why) => Some(why),
+            _ => None,
+        }
+    }
+}
+
+impl ClockError {
+    ///The command we were given was at fault, rather than the store
+    pub fn is_bad_command(&self) -> bool {
+        matches!(self, ClockError::UnknownCommand(_) | ClockError::MalformedCommand { .. } | ClockError::BadTimestamp { .. }
+            | ClockError::BadImport { .. } | ClockError::NoSuchCommand(_) | ClockError::HistoryExhausted { .. })
+    }
+}
+
+impl From<rusqlite::Error> for ClockError {
+    fn from(why: rusqlite::Error) -> Self {
+        ClockError::Storage(why)
+    }
+}
+
+impl From<std::io::Error> for ClockError {
+    fn from(why: std::io::Error) -> Self {
+        ClockError::Io(why)
+    }
+}
+
+impl From<csv::Error> for ClockError {
+    fn from(why: csv::Error) -> Self {
+        ClockError::Io(why.into())
+    }
+}
+
+impl From<serde_json::Error> for ClockError {
+    fn from(why: serde_json::Error) -> Self {
+        ClockError::Io(why.into())
+    }
+}
+
+impl From<TransitionError> for ClockError {
+    fn from(why: TransitionError) -> Self {
+        ClockError::InvalidTransition(why)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::Report;
+
+    use super::*;
+
+    #[test]
+    fn test_into_report() {
+        let report: Report = ClockError::from(TransitionError::NotTracking { task: "errors".to_string() }).into();
+        assert_eq!(report.to_string(), "not tracking 'errors'");
+        assert!(matches!(report.downcast_ref::<ClockError>(), Some(ClockError::InvalidTransition(_))));
+    }
+}
@@ -0,0 +1,453 @@
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use serde_json::Value;
+
+use crate::command::{Command, CommandType};
+use crate::db::ClockRuster;
+use crate::error::ClockError;
+use crate::output::TIMECLOCK_MARKER;
+use crate::store::ClockStore;
+
+///What an import did
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportReport {
+    ///Commands recorded
+    pub imported: usize,
+    ///Commands already stored and left alone, see is_duplicate
+    pub duplicates: usize,
+    ///Entries that were not imported, and why
+    pub skipped: Vec<String>,
+}
+
+///Is an imported command the stored one over again - same type, task, time, note and tags, in any order
+/// Anything else differing, eg a tag added since, and it is imported as a command of its own
+pub fn is_duplicate(stored: &Command, cmd: &Command) -> bool {
+    let sorted = |cmd: &Command| {
+        let mut tags = cmd.tags.clone();
+        tags.sort_unstable();
+        tags
+    };
+    stored.command == cmd.command && stored.task == cmd.task && stored.cmd_datetime == cmd.cmd_datetime
+        && stored.note == cmd.note && sorted(stored) == sorted(cmd)
+}
+
+impl Display for ImportReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "imported {}, {} already there, {} skipped", self.imported, self.duplicates, self.skipped.len())?;
+        for why in &self.skipped {
+            write!(f, "\n  {}", why)?;
+        }
+        Ok(())
+    }
+}
+
+///Commands converted from another tool's files, along with the entries that could not be converted
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Converted {
+    pub cmds: Vec<Command>,
+    ///Entries left out, and why
+    pub skipped: Vec<String>,
+}
+
+impl From<Vec<Command>> for Converted {
+    fn from(cmds: Vec<Command>) -> Self {
+        Self { cmds, skipped: Vec::new() }
+    }
+}
+
+impl Converted {
+    ///Add what was converted from another file
+    pub fn extend(&mut self, other: Converted) {
+        self.cmds.extend(other.cmds);
+        self.skipped.extend(other.skipped);
+    }
+
+    ///Import the commands, see ClockRuster::import, reporting the entries that could not be converted as skipped too
+    pub fn import_into<S: ClockStore>(self, cr: &ClockRuster<S>) -> Result<ImportReport, ClockError> {
+        let mut report = cr.import(self.cmds)?;
+        report.skipped.splice(0..0, self.skipped);
+        Ok(report)
+    }
+}
+
+///Read commands written by output::write_commands_json, either a json array or one command per line
+pub fn read_json<R: Read>(mut reader: R) -> Result<Vec<Command>, ClockError> {
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)?;
+    if contents.trim_start().starts_with('[') {
+        return serde_json::from_str(&contents)
+            .map_err(|why| ClockError::BadImport { line: why.line(), reason: why.to_string() });
+    }
+
+    let mut cmds = Vec::new();
+    for (idx, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        cmds.push(serde_json::from_str(line)
+            .map_err(|why| ClockError::BadImport { line: idx + 1, reason: why.to_string() })?);
+    }
+    Ok(cmds)
+}
+
+///Read a ledger/hledger timeclock file, as written by output::write_timeclock or by hand
+/// Times have no offset in timeclock, they are read as being in tz
+/// The account is the task and a description after it the note
+/// A clock-out without an account closes the task clocked in to most recently
+/// The `; clockrust` comments write_timeclock leaves restore everything else, see write_timeclock
+pub fn read_timeclock<R: Read, Tz: TimeZone>(mut reader: R, tz: &Tz) -> Result<Vec<Command>, ClockError> {
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)?;
+
+    let mut cmds: Vec<Command> = Vec::new();
+    //clocked in, most recent last, for clock-outs that don't name their account
+    let mut open: Vec<String> = Vec::new();
+    let mut marked_type: Option<CommandType> = None;
+    let mut marked_task: Option<String> = None;
+    let mut marked_note: Option<String> = None;
+    let mut marked_tags: Vec<String> = Vec::new();
+    for (idx, line) in contents.lines().enumerate() {
+        let bad = |reason: &str| ClockError::BadImport { line: idx + 1, reason: reason.to_string() };
+        if let Some(marker) = line.strip_prefix(TIMECLOCK_MARKER) {
+            let marker = marker.trim();
+            match marker.split_once(' ') {
+                Some(("task", task)) => marked_task = Some(serde_json::from_str(task).map_err(|why| bad(&why.to_string()))?),
+                Some(("tags", tags)) => marked_tags = serde_json::from_str(tags).map_err(|why| bad(&why.to_string()))?,
+                Some(("text", note)) => marked_note = Some(serde_json::from_str(note).map_err(|why| bad(&why.to_string()))?),
+                Some(("note", cmd)) => cmds.push(serde_json::from_str(cmd).map_err(|why| bad(&why.to_string()))?),
+                _ => marked_type = Some(marker.parse().map_err(|_| bad(&format!("unknown clockrust comment '{}'", marker)))?),
+            }
+            continue;
+        }
+        if line.trim().is_empty() || line.starts_with([';', '#', '*']) {
+            continue;
+        }
+
+        let mut fields = line.splitn(4, ' ');
+        let (code, date, time) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(code), Some(date), Some(time)) => (code, date, time),
+            _ => return Err(bad("expected a code, date and time")),
+        };
+        let naive = format!("{} {}", date.replace('/', "-"), time);
+        let naive = NaiveDateTime::parse_from_str(&naive, "%Y-%m-%d %H:%M:%S%.f")
+            .or_else(|_| NaiveDateTime::parse_from_str(&naive, "%Y-%m-%d %H:%M"))
+            .map_err(|why| bad(&why.to_string()))?;
+        let cmd_datetime = tz.from_local_datetime(&naive).earliest()
+            .ok_or_else(|| bad("time does not exist in the timezone"))?
+            .with_timezone(&Utc);
+        //two spaces or a tab end the account, the description follows
+        let rest = fields.next().unwrap_or("");
+        let (account, description) = match rest.find("  ").into_iter().chain(rest.find('\t')).min() {
+            Some(end) => (rest[..end].trim(), Some(rest[end..].trim()).filter(|description| !description.is_empty())),
+            None => (rest.trim(), None),
+        };
+
+        let (default_type, task) = match code {
+            "i" | "I" => {
+                if account.is_empty() {
+                    return Err(bad("clock-in without an account"));
+                }
+                (CommandType::ClockIn, marked_task.take().unwrap_or_else(|| account.to_string()))
+            }
+            "o" | "O" => {
+                let task = match marked_task.take() {
+                    Some(task) => task,
+                    None if !account.is_empty() => account.to_string(),
+                    None => open.last().cloned().ok_or_else(|| bad("clock-out with nothing clocked in"))?,
+                };
+                (CommandType::ClockOut, task)
+            }
+            _ => return Err(bad(&format!("unknown timeclock code '{}'", code))),
+        };
+        let command = marked_type.take().unwrap_or(default_type);
+        match command {
+            CommandType::ClockIn | CommandType::Switch => open.push(task.clone()),
+            CommandType::ClockOut => open.retain(|open_task| *open_task != task),
+            CommandType::Pause | CommandType::Resume | CommandType::Note => {}
+        }
+        let mut cmd = Command::new(command, cmd_datetime, task);
+        cmd.note = marked_note.take().or_else(|| description.map(String::from));
+        cmd.tags = std::mem::take(&mut marked_tags);
+        cmds.push(cmd);
+    }
+    Ok(cmds)
+}
+
+///Read a timewarrior data file, one of its data/YYYY-MM.data files
+/// eg `inc 20220131T090000Z - 20220131T100000Z # client docs # "annotation"`
+/// Each interval becomes a clock-in and, unless it is still open, a clock-out
+/// The task is the interval's tags joined with spaces, intervals without tags are skipped
+/// The tags are kept as the clock-in's tags too, and an annotation becomes its note
+pub fn read_timewarrior<R: Read>(mut reader: R) -> Result<Converted, ClockError> {
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)?;
+
+    let mut converted = Converted::default();
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match timewarrior_interval(line) {
+            Ok(cmds) => converted.cmds.extend(cmds),
+            Err(why) => converted.skipped.push(format!("line {}: {} ({})", idx + 1, why, line)),
+        }
+    }
+    Ok(converted)
+}
+
+///Commands for a timewarrior interval line, or why it can't be imported
+fn timewarrior_interval(line: &str) -> Result<Vec<Command>, String> {
+    let interval = line.strip_prefix("inc ").ok_or("not an interval")?;
+    let (range, tags, annotation) = match interval.split_once(" # ") {
+        Some((range, rest)) => match rest.split_once(" # ") {
+            Some((tags, annotation)) => (range, tags, Some(annotation.trim())),
+            None => (range, rest, None),
+        },
+        None => (interval, "", None),
+    };
+    let timestamp = |ts: &str| NaiveDateTime::parse_from_str(ts.trim(), "%Y%m%dT%H%M%SZ")
+        .map(|naive| naive.and_utc())
+        .map_err(|why| format!("bad time '{}': {}", ts.trim(), why));
+    let (start, end) = match range.split_once(" - ") {
+        Some((start, end)) => (timestamp(start)?, Some(timestamp(end)?)),
+        None => (timestamp(range)?, None),
+    };
+    if end.is_some_and(|end| end < start) {
+        return Err("ends before it starts".to_string());
+    }
+    let tags = timewarrior_tags(tags);
+    let task = tags.join(" ");
+    if task.is_empty() {
+        return Err("no tags to name the task".to_string());
+    }
+    let mut clock_in = Command::new(CommandType::ClockIn, start, task.clone());
+    clock_in.note = annotation.map(|annotation| {
+        let unquoted = annotation.strip_prefix('"').and_then(|a| a.strip_suffix('"')).unwrap_or(annotation);
+        unquoted.replace("\\\"", "\"")
+    });
+    clock_in.tags = tags;
+    let mut cmds = vec![clock_in];
+    if let Some(end) = end {
+        cmds.push(Command::new(CommandType::ClockOut, end, task));
+    }
+    Ok(cmds)
+}
+
+///Split timewarrior tags on spaces, tags with spaces in them are double quoted
+fn timewarrior_tags(tags: &str) -> Vec<String> {
+    let mut split = Vec::new();
+    let mut tag = String::new();
+    let mut quoted = false;
+    let mut chars = tags.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' => tag.extend(chars.next()),
+            ' ' if !quoted => {
+                if !tag.is_empty() {
+                    split.push(std::mem::take(&mut tag));
+                }
+            }
+            c => tag.push(c),
+        }
+    }
+    if !tag.is_empty() {
+        split.push(tag);
+    }
+    split
+}
+
+///Read Watson's frames file, a json array of `[start, stop, project, id, tags, updated_at]` frames
+/// Each frame becomes a clock-in and a clock-out on its project, the frame's tags go on the clock-in
+/// Frames that don't look like that are skipped
+pub fn read_watson<R: Read>(reader: R) -> Result<Converted, ClockError> {
+    let frames: Vec<Value> = serde_json::from_reader(reader)
+        .map_err(|why| ClockError::BadImport { line: why.line(), reason: why.to_string() })?;
+
+    let mut converted = Converted::default();
+    for (idx, frame) in frames.iter().enumerate() {
+        match watson_frame(frame) {
+            Ok(cmds) => converted.cmds.extend(cmds),
+            Err(why) => converted.skipped.push(format!("frame {}: {} ({})", idx + 1, why, frame)),
+        }
+    }
+    Ok(converted)
+}
+
+///Commands for a Watson frame, or why it can't be imported
+fn watson_frame(frame: &Value) -> Result<Vec<Command>, String> {
+    let timestamp = |value: Option<&Value>| value.and_then(Value::as_i64)
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .ok_or("bad start or stop time");
+    let start = timestamp(frame.get(0))?;
+    let stop = timestamp(frame.get(1))?;
+    let project = frame.get(2).and_then(Value::as_str).filter(|project| !project.is_empty()).ok_or("no project")?;
+    if stop < start {
+        return Err("stops before it starts".to_string());
+    }
+    let mut clock_in = Command::new(CommandType::ClockIn, start, project.to_string());
+    clock_in.tags = frame.get(4).and_then(Value::as_array).into_iter().flatten()
+        .filter_map(Value::as_str)
+        .map(String::from)
+        .collect();
+    Ok(vec![clock_in, Command::new(CommandType::ClockOut, stop, project.to_string())])
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::Report;
+
+    use crate::command::CommandType;
+    use crate::db::tests::{create_test_cmd, TEST_TASK, TEST_TASK_2};
+    use chrono::FixedOffset;
+
+    use crate::output::{write_commands_json, write_timeclock, JsonFormat};
+
+    use super::*;
+
+    fn test_cmds() -> Vec<Command> {
+        vec![
+            create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00"),
+            create_test_cmd(CommandType::Pause, TEST_TASK, "2022-01-31 10:00:00.5+00:00"),
+            create_test_cmd(CommandType::ClockIn, TEST_TASK_2, "2022-01-31 11:00:00+00:00"),
+        ]
+    }
+
+    #[test]
+    fn test_json_round_trip() -> Result<(), Report> {
+        for format in [JsonFormat::Json, JsonFormat::Ndjson] {
+            let mut out = Vec::new();
+            write_commands_json(&mut out, &test_cmds(), format)?;
+            assert_eq!(read_json(out.as_slice())?, test_cmds(), "{:?} did not round trip", format);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_ndjson_layout() -> Result<(), Report> {
+        let mut out = Vec::new();
+        write_commands_json(&mut out, &test_cmds()[..1], JsonFormat::Ndjson)?;
+        assert_eq!(String::from_utf8(out)?, format!("{{\"command\":\"clock-in\",\"cmd_datetime\":\"2022-01-31T09:00:00Z\",\"task\":\"{}\"}}\n", TEST_TASK));
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_line() {
+        let ndjson = "{\"command\":\"clock-in\",\"cmd_datetime\":\"2022-01-31T09:00:00Z\",\"task\":\"fine\"}\n\n{\"command\":\"clock-sideways\"}\n";
+        match read_json(ndjson.as_bytes()) {
+            Err(ClockError::BadImport { line, .. }) => assert_eq!(line, 3),
+            other => panic!("Expected a bad import, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_timeclock_round_trip() -> Result<(), Report> {
+        let mut cmds = test_cmds();
+        cmds.push(create_test_cmd(CommandType::Resume, TEST_TASK, "2022-01-31 10:30:00+00:00"));
+        cmds.push(create_test_cmd(CommandType::ClockIn, "  spaced  out\ttask ", "2022-01-31 11:30:00+00:00"));
+        cmds.push(create_test_cmd(CommandType::ClockOut, TEST_TASK, "2022-01-31 12:00:00+00:00"));
+        let mut note = create_test_cmd(CommandType::Note, TEST_TASK, "2022-01-31 10:45:00+00:00");
+        note.note = Some("found it".to_string());
+        cmds.push(note);
+        cmds[0].note = Some("drafting  it".to_string());
+        cmds[3].note = Some("back\nagain".to_string());
+        cmds[2].tags = vec!["billable".to_string(), "client work".to_string()];
+        cmds.sort_by_key(|cmd| cmd.cmd_datetime);
+        let tz = FixedOffset::east_opt(2 * 3600).unwrap();
+        let mut out = Vec::new();
+        write_timeclock(&mut out, &cmds, &tz)?;
+        let written = String::from_utf8(out)?;
+        assert!(written.starts_with(&format!("i 2022-01-31 11:00:00 {}  drafting  it\n; clockrust pause\no 2022-01-31 12:00:00.500 {}\n", TEST_TASK, TEST_TASK)));
+        assert!(written.contains("; clockrust resume\n; clockrust text \"back\\nagain\"\n"));
+        assert!(written.contains("; clockrust task \"  spaced  out\\ttask \"\ni 2022-01-31 13:30:00 spaced out task\n"));
+        assert!(written.contains("; clockrust tags [\"billable\",\"client work\"]\ni 2022-01-31 13:00:00 "));
+        assert_eq!(read_timeclock(written.as_bytes(), &tz)?, cmds);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_hledger_timeclock() -> Result<(), Report> {
+        let timeclock = "\
+; from hledger
+i 2021/10/31 04:10 client:project  writing the docs
+o 2021/10/31 05:00:30
+i 2021-10-31 06:00:00 other
+i 2021-10-31 06:30:00 client:project
+o 2021-10-31 07:00:00 other
+o 2021-10-31 07:30:00
+";
+        let cmds = read_timeclock(timeclock.as_bytes(), &Utc)?;
+        let summary: Vec<(CommandType, &str)> = cmds.iter().map(|cmd| (cmd.command.clone(), cmd.task.as_str())).collect();
+        assert_eq!(summary, vec![
+            (CommandType::ClockIn, "client:project"),
+            (CommandType::ClockOut, "client:project"),
+            (CommandType::ClockIn, "other"),
+            (CommandType::ClockIn, "client:project"),
+            (CommandType::ClockOut, "other"),
+            (CommandType::ClockOut, "client:project"),
+        ]);
+        assert_eq!(cmds[1].cmd_datetime, "2021-10-31 05:00:30+00:00".parse::<chrono::DateTime<Utc>>()?);
+        assert_eq!(cmds[0].note.as_deref(), Some("writing the docs"));
+
+        match read_timeclock("o 2021-10-31 07:30:00\n".as_bytes(), &Utc) {
+            Err(ClockError::BadImport { line, .. }) => assert_eq!(line, 1),
+            other => panic!("Expected a bad import, got {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_timewarrior() -> Result<(), Report> {
+        let data = r#"inc 20220131T090000Z - 20220131T100000Z # client "write docs" # "first draft"
+inc 20220131T103000Z - 20220131T101500Z # backwards
+inc 20220131T110000Z - 20220131T113000Z
+exc monday 12:00:00-13:00:00
+inc 20220131T140000Z # client "write docs"
+"#;
+        let converted = read_timewarrior(data.as_bytes())?;
+        let summary: Vec<(CommandType, &str)> = converted.cmds.iter().map(|cmd| (cmd.command.clone(), cmd.task.as_str())).collect();
+        assert_eq!(summary, vec![
+            (CommandType::ClockIn, "client write docs"),
+            (CommandType::ClockOut, "client write docs"),
+            (CommandType::ClockIn, "client write docs"),
+        ]);
+        assert_eq!(converted.cmds[1].cmd_datetime, "2022-01-31 10:00:00+00:00".parse::<DateTime<Utc>>()?);
+        assert_eq!(converted.cmds[0].note.as_deref(), Some("first draft"));
+        assert_eq!(converted.cmds[0].tags, vec!["client".to_string(), "write docs".to_string()]);
+        assert_eq!(converted.skipped.len(), 3);
+        assert!(converted.skipped[0].starts_with("line 2: ends before it starts"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_watson() -> Result<(), Report> {
+        let frames = r#"[
+            [1643619600, 1643623200, "write docs", "a1b2", ["draft"], 1643623200],
+            [1643623200, 1643619600, "backwards", "c3d4", [], 1643623200],
+            ["soon", 1643619600, "bad time", "e5f6", [], 1643623200]
+        ]"#;
+        let converted = read_watson(frames.as_bytes())?;
+        let mut clock_in = create_test_cmd(CommandType::ClockIn, "write docs", "2022-01-31 09:00:00+00:00");
+        clock_in.tags = vec!["draft".to_string()];
+        assert_eq!(converted.cmds, vec![
+            clock_in,
+            create_test_cmd(CommandType::ClockOut, "write docs", "2022-01-31 10:00:00+00:00"),
+        ]);
+        assert_eq!(converted.skipped.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reimport_is_idempotent() -> Result<(), Report> {
+        let data = "inc 20220131T090000Z - 20220131T100000Z # docs\ninc 20220131T100000Z - 20220131T110000Z # docs\ninc nonsense\n";
+        let cr = ClockRuster::in_memory();
+        let first = read_timewarrior(data.as_bytes())?.import_into(&cr)?;
+        let again = read_timewarrior(data.as_bytes())?.import_into(&cr)?;
+        assert_eq!((first.imported, first.duplicates, first.skipped.len()), (4, 0, 1));
+        assert_eq!((again.imported, again.duplicates, again.skipped.len()), (0, 4, 1));
+        assert_eq!(cr.sessions(None, None, None)?.sessions.len(), 2);
+        Ok(())
+    }
+}
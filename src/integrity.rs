@@ -0,0 +1,151 @@
+use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::command::Command;
+
+///Chain hash the first command links to
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+///Sha-256, in hex, of the previous link and everything stored for the command, id included
+/// Changing any command, or removing or reordering them, changes the link of every command after it
+pub fn link(previous: &str, cmd: &Command) -> String {
+    let mut tags: Vec<&str> = cmd.tags.iter().map(String::as_str).collect();
+    tags.sort_unstable();
+    //json, so no field can run into the next
+    let mut fields = vec![
+        json!(cmd.id),
+        json!(cmd.command.to_string()),
+        json!(cmd.task),
+        json!(cmd.cmd_datetime.to_rfc3339()),
+        json!(cmd.note),
+        json!(tags),
+    ];
+    //only when set, so commands sealed before there was a flag still check out
+    if cmd.system {
+        fields.push(json!(true));
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(previous.as_bytes());
+    hasher.update(Value::Array(fields).to_string().as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+///The link for each command in turn, starting from GENESIS
+pub fn seal(cmds: &[Command]) -> Vec<String> {
+    let mut previous = GENESIS.to_string();
+    cmds.iter()
+        .map(|cmd| {
+            previous = link(&previous, cmd);
+            previous.clone()
+        })
+        .collect()
+}
+
+///The first command whose stored link is not the one its content and the command before it make
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BrokenLink {
+    pub id: i64,
+    pub expected: String,
+    ///None for a command stored without a link at all
+    pub found: Option<String>,
+}
+
+///What verifying the chain found
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntegrityReport {
+    ///Commands whose links checked out
+    pub verified: usize,
+    ///Link of the last command checked out, GENESIS for an empty store
+    /// Keep it somewhere else, eg with an invoice, to show later that nothing up to it has changed since
+    pub head: String,
+    pub broken: Option<BrokenLink>,
+}
+
+impl IntegrityReport {
+    ///Walk the chain, commands in id order with the links stored for them, stopping at the first broken link
+    pub fn verify(chain: &[(Command, Option<String>)]) -> Self {
+        Self::verify_from(GENESIS, chain)
+    }
+
+    ///verify, for a chain picking up after the command linked as head
+    pub fn verify_from(head: &str, chain: &[(Command, Option<String>)]) -> Self {
+        let mut report = Self { verified: 0, head: head.to_string(), broken: None };
+        for (cmd, found) in chain {
+            let expected = link(&report.head, cmd);
+            if found.as_deref() != Some(expected.as_str()) {
+                report.broken = Some(BrokenLink { id: cmd.id.unwrap_or_default(), expected, found: found.clone() });
+                break;
+            }
+            report.verified += 1;
+            report.head = expected;
+        }
+        report
+    }
+
+    pub fn is_intact(&self) -> bool {
+        self.broken.is_none()
+    }
+}
+
+impl Display for IntegrityReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.broken {
+            None => write!(f, "{} commands intact, head {}", self.verified, self.head),
+            Some(broken) => write!(f, "Chain broken at command #{} after {} intact commands: expected {}, found {}",
+                broken.id, self.verified, broken.expected, broken.found.as_deref().unwrap_or("nothing")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::CommandType;
+    use crate::db::tests::{create_test_cmd, TEST_TASK};
+
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let mut cmds: Vec<Command> = ["2022-01-31 09:00:00+00:00", "2022-01-31 10:00:00+00:00", "2022-01-31 11:00:00+00:00"].iter()
+            .zip(1..)
+            .map(|(dt, id)| {
+                let mut cmd = create_test_cmd(CommandType::ClockIn, TEST_TASK, dt);
+                cmd.id = Some(id);
+                cmd
+            })
+            .collect();
+        cmds[0].tags = vec!["b".to_string(), "a".to_string()];
+        let links = seal(&cmds);
+        let chain: Vec<(Command, Option<String>)> = cmds.iter().cloned().zip(links.iter().cloned().map(Some)).collect();
+
+        let report = IntegrityReport::verify(&chain);
+        assert!(report.is_intact());
+        assert_eq!(report.verified, 3);
+        assert_eq!(report.head, links[2]);
+        assert_eq!(IntegrityReport::verify(&[]).head, GENESIS);
+
+        //tag order is not content
+        let mut reordered = chain.clone();
+        reordered[0].0.tags.reverse();
+        assert!(IntegrityReport::verify(&reordered).is_intact());
+
+        let mut edited = chain.clone();
+        edited[1].0.cmd_datetime = "2022-01-31 10:30:00+00:00".parse().unwrap();
+        let report = IntegrityReport::verify(&edited);
+        assert_eq!(report.verified, 1);
+        assert_eq!(report.broken.map(|broken| broken.id), Some(2));
+
+        //dropping a command breaks the one after it
+        let mut dropped = chain.clone();
+        dropped.remove(1);
+        assert_eq!(IntegrityReport::verify(&dropped).broken.map(|broken| broken.id), Some(3));
+
+        let mut unsealed = chain;
+        unsealed[2].1 = None;
+        let report = IntegrityReport::verify(&unsealed);
+        assert_eq!(report.broken.as_ref().and_then(|broken| broken.found.clone()), None);
+        assert!(report.to_string().starts_with("Chain broken at command #3 after 2 intact commands"));
+    }
+}
@@ -0,0 +1,232 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::command::Command;
+use crate::error::ClockError;
+
+///What was done to a single stored command
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangeAction {
+    Insert,
+    Amend,
+    Delete,
+}
+
+impl Display for ChangeAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChangeAction::Insert => write!(f, "insert"),
+            ChangeAction::Amend => write!(f, "amend"),
+            ChangeAction::Delete => write!(f, "delete"),
+        }
+    }
+}
+
+impl FromStr for ChangeAction {
+    type Err = ClockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "insert" => Ok(ChangeAction::Insert),
+            "amend" => Ok(ChangeAction::Amend),
+            "delete" => Ok(ChangeAction::Delete),
+            _ => Err(ClockError::CorruptData(format!("unknown journal change '{}'", s))),
+        }
+    }
+}
+
+///A single stored command changing, with the command, id included, as it was and as it became
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    pub action: ChangeAction,
+    ///None for an insert
+    pub before: Option<Command>,
+    ///None for a delete
+    pub after: Option<Command>,
+}
+
+impl Change {
+    pub fn insert(after: Command) -> Self {
+        Self { action: ChangeAction::Insert, before: None, after: Some(after) }
+    }
+
+    pub fn amend(before: Command, after: Command) -> Self {
+        Self { action: ChangeAction::Amend, before: Some(before), after: Some(after) }
+    }
+
+    pub fn delete(before: Command) -> Self {
+        Self { action: ChangeAction::Delete, before: Some(before), after: None }
+    }
+
+    ///Id of the command that changed
+    pub fn command_id(&self) -> Option<i64> {
+        self.after.as_ref().or(self.before.as_ref()).and_then(|cmd| cmd.id)
+    }
+
+    ///The change that takes this one back
+    pub fn inverse(&self) -> Self {
+        let action = match self.action {
+            ChangeAction::Insert => ChangeAction::Delete,
+            ChangeAction::Amend => ChangeAction::Amend,
+            ChangeAction::Delete => ChangeAction::Insert,
+        };
+        Self { action, before: self.after.clone(), after: self.before.clone() }
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} #{}", self.action, self.command_id().unwrap_or_default())?;
+        match (&self.before, &self.after) {
+            (Some(before), Some(after)) => write!(f, ": {} -> {}", before, after),
+            (Some(cmd), None) | (None, Some(cmd)) => write!(f, ": {}", cmd),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+///Why a journal entry was written
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EntryKind {
+    ///Something done through ClockRuster
+    Record,
+    ///Taking back the entry it targets
+    Undo,
+    ///Taking back the undo it targets
+    Redo,
+}
+
+impl Display for EntryKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntryKind::Record => write!(f, "record"),
+            EntryKind::Undo => write!(f, "undo"),
+            EntryKind::Redo => write!(f, "redo"),
+        }
+    }
+}
+
+impl FromStr for EntryKind {
+    type Err = ClockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "record" => Ok(EntryKind::Record),
+            "undo" => Ok(EntryKind::Undo),
+            "redo" => Ok(EntryKind::Redo),
+            _ => Err(ClockError::CorruptData(format!("unknown journal entry kind '{}'", s))),
+        }
+    }
+}
+
+///One operation done through ClockRuster, with every command it changed
+/// The journal is only ever appended to, an undo is an entry of its own
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    ///Id the store keeps the entry under, None until it has been appended
+    pub id: Option<i64>,
+    pub kind: EntryKind,
+    ///For an undo or redo, the entry it takes back
+    pub target: Option<i64>,
+    ///When the operation was done
+    pub at: DateTime<Utc>,
+    ///What was done, eg `clock-in 'write docs'`
+    pub description: String,
+    ///In the order they were made
+    pub changes: Vec<Change>,
+}
+
+impl Display for JournalEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{} {} {}", self.id.unwrap_or_default(), self.at.to_rfc3339(), self.kind)?;
+        if let Some(target) = self.target {
+            write!(f, " #{}", target)?;
+        }
+        write!(f, ": {}", self.description)?;
+        for change in &self.changes {
+            write!(f, "\n  {}", change)?;
+        }
+        Ok(())
+    }
+}
+
+///Entries that can be undone and undos that can be redone, most recent last
+/// Recording anything new forgets what could have been redone, as in an editor
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UndoStack {
+    ///Record and redo entries still in effect
+    pub undoable: Vec<i64>,
+    ///Undo entries not yet taken back
+    pub redoable: Vec<i64>,
+}
+
+impl UndoStack {
+    ///Replay the journal, oldest entry first
+    pub fn from_journal(entries: &[JournalEntry]) -> Self {
+        let mut stack = Self::default();
+        for entry in entries {
+            let Some(id) = entry.id else { continue };
+            match entry.kind {
+                EntryKind::Record => {
+                    stack.undoable.push(id);
+                    stack.redoable.clear();
+                }
+                EntryKind::Undo => {
+                    stack.undoable.pop();
+                    stack.redoable.push(id);
+                }
+                EntryKind::Redo => {
+                    stack.redoable.pop();
+                    stack.undoable.push(id);
+                }
+            }
+        }
+        stack
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::CommandType;
+    use crate::db::tests::{create_test_cmd, TEST_TASK};
+
+    use super::*;
+
+    fn entry(id: i64, kind: EntryKind) -> JournalEntry {
+        JournalEntry { id: Some(id), kind, target: None, at: Utc::now(), description: String::new(), changes: Vec::new() }
+    }
+
+    #[test]
+    fn test_inverse() {
+        let mut before = create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00");
+        before.id = Some(3);
+        let mut after = before.clone();
+        after.task = "moved".to_string();
+
+        let insert = Change::insert(before.clone());
+        assert_eq!(insert.inverse(), Change::delete(before.clone()));
+        assert_eq!(insert.inverse().inverse(), insert);
+        assert_eq!(Change::amend(before.clone(), after.clone()).inverse(), Change::amend(after, before));
+        assert_eq!(insert.command_id(), Some(3));
+    }
+
+    #[test]
+    fn test_undo_stack() {
+        let journal = vec![
+            entry(1, EntryKind::Record),
+            entry(2, EntryKind::Record),
+            entry(3, EntryKind::Undo),
+            entry(4, EntryKind::Undo),
+            entry(5, EntryKind::Redo),
+        ];
+        assert_eq!(UndoStack::from_journal(&journal), UndoStack { undoable: vec![5], redoable: vec![3] });
+
+        let mut journal = journal;
+        journal.push(entry(6, EntryKind::Record));
+        assert_eq!(UndoStack::from_journal(&journal), UndoStack { undoable: vec![5, 6], redoable: Vec::new() });
+    }
+}
@@ -1,9 +1,17 @@
+//!Library for adding time tracking to an application
 pub mod command;
 pub mod config;
 pub mod db;
+pub mod error;
+pub mod import;
+pub mod integrity;
+pub mod journal;
+pub mod migration;
 pub mod output;
-
-///Library for adding time tracking to an application
+pub mod policy;
+pub mod project;
+pub mod session;
+pub mod store;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,491 @@
+use std::fs::{self, File};
+use std::io;
+use std::process::ExitCode;
+use chrono::{DateTime, Duration, Local, NaiveTime, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use clockrusting::command::{create_command, parse_duration, parse_time, Command, CommandType};
+use clockrusting::config;
+use clockrusting::db::ClockRuster;
+use clockrusting::import;
+use clockrusting::output;
+use clockrusting::error::ClockError;
+use clockrusting::policy::{StalePolicy, TrackingPolicy};
+use clockrusting::session::SessionList;
+use clockrusting::store::{ClockStore, CommandFilter};
+
+///Exit code when the command we were given could not be understood
+const EXIT_BAD_COMMAND: u8 = 2;
+///Exit code when the storage could not be read or written
+const EXIT_STORAGE: u8 = 3;
+///Exit code when the tracking policy refused a command, or an edit would have left commands unpaired
+const EXIT_INVALID_TRANSITION: u8 = 4;
+
+///Time tracking in sqlite
+#[derive(Parser)]
+#[command(name = "clockrust", version, author = "foom")]
+struct Cli {
+    ///SQLite file where we store times, ./.clockrust if not given
+    #[arg(short, long, global = true)]
+    file: Option<String>,
+    ///Log much information
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    ///How strictly to check commands against the tasks already open
+    #[arg(long, global = true, value_enum, default_value_t = Policy::Permissive)]
+    policy: Policy,
+    ///Sessions open longer than this, eg 12h, need reviewing
+    #[arg(long, global = true, value_parser = parse_max_session)]
+    max_session: Option<Duration>,
+    ///Sessions still open at this local time, eg 19:00, need reviewing
+    #[arg(long, global = true, value_parser = parse_end_of_day)]
+    end_of_day: Option<NaiveTime>,
+    #[command(subcommand)]
+    command: Cmd,
+}
+
+#[derive(Subcommand)]
+enum Cmd {
+    ///Clock in to a task
+    In(ClockArgs),
+    ///Clock out of a task
+    Out(ClockArgs),
+    ///Pause a task without clocking out
+    Pause(ClockArgs),
+    ///Carry on with a paused task
+    Resume(ClockArgs),
+    ///Clock out of everything open and clock in to this task
+    Switch(ClockArgs),
+    ///Add a note to a task's open session
+    Note {
+        task: String,
+        note: String,
+        ///When, in any form --at takes, defaults to now
+        #[arg(long, allow_hyphen_values = true)]
+        at: Option<String>,
+    },
+    ///Are we tracking a task, or, without one, every open task with its start and time so far
+    Status {
+        task: Option<String>,
+    },
+    ///Clock out of every session gone stale under --max-session or --end-of-day, where it went stale
+    CloseStale,
+    ///List sessions, pairing clock-ins with clock-outs
+    List(FilterArgs),
+    ///Per-task summary of time spent
+    Report {
+        ///Write the report to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+        ///What to sum time by
+        #[arg(long, value_enum, default_value_t = Grouping::Task)]
+        by: Grouping,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    ///Every command recorded, as stored
+    Log(FilterArgs),
+    ///Projects tasks are filed under, as a tree
+    Projects,
+    ///Move a recorded command to another time or task, by the id log shows
+    Amend {
+        id: i64,
+        ///New time, in any form --at takes
+        #[arg(long, allow_hyphen_values = true, value_parser = parse_when)]
+        at: Option<DateTime<Utc>>,
+        ///New task
+        #[arg(long)]
+        task: Option<String>,
+    },
+    ///Forget a recorded command, by the id log shows
+    Delete {
+        id: i64,
+    },
+    ///Every operation recorded, and every undo and redo of one
+    Journal,
+    ///Take back the last operations, a clock-in, amend, import and so on each being one
+    Undo {
+        ///How many operations
+        #[arg(default_value_t = 1)]
+        n: usize,
+    },
+    ///Take back the last undos
+    Redo {
+        ///How many undos
+        #[arg(default_value_t = 1)]
+        n: usize,
+    },
+    ///Check no recorded command has been changed other than through clockrust
+    Verify,
+    ///Write out commands for other tools or for backup
+    Export {
+        #[arg(long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        ///Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    ///Record commands from files, skipping any already recorded
+    Import {
+        #[arg(required = true)]
+        inputs: Vec<String>,
+        #[arg(long, value_enum, default_value_t = ImportFormat::Json)]
+        format: ImportFormat,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    ///A json array of commands
+    Json,
+    ///One json command per line
+    Ndjson,
+    ///One csv row per command
+    Csv,
+    ///ledger/hledger timeclock, in local time
+    Timeclock,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Grouping {
+    ///One row per task
+    Task,
+    ///One row per tag, sessions with several tags count under each
+    Tag,
+    ///A tree of projects, time rolled up from the tasks below
+    Project,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ImportFormat {
+    ///json or ndjson, as written by export
+    Json,
+    ///ledger/hledger timeclock, in local time
+    Timeclock,
+    ///timewarrior data/*.data files, tags name the task
+    Timewarrior,
+    ///Watson's frames file, projects name the task
+    Watson,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Policy {
+    ///Anything goes
+    Permissive,
+    ///Many tasks can be open, each command has to make sense for its task
+    Multi,
+    ///One task open at a time
+    Single,
+    ///One task open at a time, clocking in closes the others
+    SingleAutoClose,
+}
+
+impl From<Policy> for TrackingPolicy {
+    fn from(policy: Policy) -> Self {
+        match policy {
+            Policy::Permissive => TrackingPolicy::Permissive,
+            Policy::Multi => TrackingPolicy::MultiTask,
+            Policy::Single => TrackingPolicy::SingleTask { auto_close: false },
+            Policy::SingleAutoClose => TrackingPolicy::SingleTask { auto_close: true },
+        }
+    }
+}
+
+#[derive(Args)]
+struct ClockArgs {
+    task: String,
+    ///When: rfc3339, now, -15m, 09:30 or yesterday 17:00, defaults to now
+    #[arg(long, allow_hyphen_values = true)]
+    at: Option<String>,
+    ///What was done, or is about to be
+    #[arg(long)]
+    note: Option<String>,
+    ///Tag the command, can be given more than once
+    #[arg(long)]
+    tag: Vec<String>,
+}
+
+#[derive(Args)]
+struct FilterArgs {
+    ///Only from this time, in any form --at takes
+    #[arg(long, allow_hyphen_values = true, value_parser = parse_when)]
+    from: Option<DateTime<Utc>>,
+    ///Only up to this time, in any form --at takes
+    #[arg(long, allow_hyphen_values = true, value_parser = parse_when)]
+    to: Option<DateTime<Utc>>,
+    ///Only this task
+    #[arg(long)]
+    task: Option<String>,
+    ///Only commands with a note containing this, for log and export
+    #[arg(long)]
+    search: Option<String>,
+    ///Only commands, or for list and report sessions, with this tag, can be given more than once
+    #[arg(long)]
+    tag: Vec<String>,
+    ///Only tasks in this project or below it, eg clientA for clientA/website/login-bug
+    #[arg(long)]
+    project: Option<String>,
+}
+
+impl FilterArgs {
+    fn command_filter(&self) -> CommandFilter {
+        let filter = CommandFilter::new(self.from, self.to, self.task.as_deref());
+        let filter = match &self.search {
+            Some(text) => filter.with_note_search(text),
+            None => filter,
+        };
+        let filter = match &self.project {
+            Some(path) => filter.with_project(path),
+            None => filter,
+        };
+        self.tag.iter().fold(filter, |filter, tag| filter.with_tag(tag))
+    }
+
+    ///Sessions within the limits, tags are checked against whole sessions rather than single commands
+    fn sessions(&self, cr: &ClockRuster) -> Result<SessionList, ClockError> {
+        let mut filter = CommandFilter::new(self.from, self.to, self.task.as_deref());
+        filter.project = self.project.clone();
+        let sessions = cr.sessions_matching(&filter)?;
+        Ok(self.tag.iter().fold(sessions, |sessions, tag| sessions.tagged(tag)))
+    }
+}
+
+fn parse_when(time_str: &str) -> Result<DateTime<Utc>, ClockError> {
+    parse_time(time_str, &Local::now())
+}
+
+fn parse_max_session(duration_str: &str) -> Result<Duration, String> {
+    parse_duration(duration_str).ok_or_else(|| format!("'{}' is not a length of time such as 12h or 1h30m", duration_str))
+}
+
+fn parse_end_of_day(time_str: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(time_str, "%H:%M").map_err(|_| format!("'{}' is not a time of day such as 19:00", time_str))
+}
+
+///Exit code to report the error with
+fn exit_code(error: &ClockError) -> u8 {
+    match error {
+        ClockError::InvalidTransition(_) | ClockError::InvalidEdit { .. } => EXIT_INVALID_TRANSITION,
+        e if e.is_bad_command() => EXIT_BAD_COMMAND,
+        _ => EXIT_STORAGE,
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    config::setup_logging(cli.verbose);
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            ExitCode::from(exit_code(&error))
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<(), ClockError> {
+    let cr = match &cli.file {
+        Some(file) => ClockRuster::init(file),
+        None => ClockRuster::new(),
+    }.with_policy(cli.policy.into())
+        .with_stale_policy(StalePolicy {
+            max_duration: cli.max_session,
+            end_of_day: cli.end_of_day,
+        });
+
+    match cli.command {
+        Cmd::In(args) => clock(&cr, CommandType::ClockIn, args),
+        Cmd::Out(args) => clock(&cr, CommandType::ClockOut, args),
+        Cmd::Pause(args) => clock(&cr, CommandType::Pause, args),
+        Cmd::Resume(args) => clock(&cr, CommandType::Resume, args),
+        Cmd::Switch(args) => clock(&cr, CommandType::Switch, args),
+        Cmd::Note { task, note, at } => clock(&cr, CommandType::Note, ClockArgs { task, at, note: Some(note), tag: Vec::new() }),
+        Cmd::Status { task: Some(task) } => {
+            let state = cr.task_state(&task)?;
+            println!("'{}' is {}", task, state);
+            Ok(())
+        }
+        Cmd::Status { task: None } => {
+            let status = cr.status()?;
+            if status.is_empty() {
+                println!("Nothing open");
+            }
+            status.iter()
+                .for_each(|open| println!("'{}' {} since '{}' [{}]{}", open.task, open.state, open.since.to_rfc3339(),
+                    output::format_duration(open.elapsed), if open.needs_review { " needs review" } else { "" }));
+            Ok(())
+        }
+        Cmd::CloseStale => {
+            let closed = cr.close_stale()?;
+            if closed.is_empty() {
+                println!("Nothing stale");
+            }
+            closed.iter().for_each(|clock_out| println!("{}", clock_out));
+            Ok(())
+        }
+        Cmd::List(filter) => {
+            let sessions = filter.sessions(&cr)?;
+            sessions.sessions.iter()
+                .for_each(|session| match session.needs_review() {
+                    true => println!("{} [{}] {}", session, output::format_duration(session.duration), output::review_reason(session)),
+                    false => println!("{} [{}]", session, output::format_duration(session.duration)),
+                });
+            sessions.anomalies.iter()
+                .for_each(|anomaly| println!("! {}", anomaly));
+            Ok(())
+        }
+        Cmd::Report { output: file, by, filter } => {
+            let sessions = filter.sessions(&cr)?;
+            let report = match by {
+                Grouping::Task => output::summary_report(&sessions),
+                Grouping::Tag => output::tag_report(&sessions),
+                Grouping::Project => output::project_report(&sessions),
+            };
+            match file {
+                Some(path) => fs::write(path, report)?,
+                None => println!("{}", report),
+            }
+            Ok(())
+        }
+        Cmd::Log(filter) => {
+            let cmds = cr.commands(&filter.command_filter())?;
+            cmds.iter().for_each(|cmd| {
+                let id = cmd.id.map(|id| format!("#{} ", id)).unwrap_or_default();
+                let tags: String = cmd.tags.iter().map(|tag| format!(" +{}", tag)).collect();
+                let system = if cmd.system { " (system)" } else { "" };
+                match &cmd.note {
+                    Some(note) => println!("{}{}{}{} - {}", id, cmd, tags, system, note),
+                    None => println!("{}{}{}{}", id, cmd, tags, system),
+                }
+            });
+            Ok(())
+        }
+        Cmd::Projects => {
+            cr.projects()?.iter()
+                .for_each(|project| println!("{}{}", "  ".repeat(project.depth()), project.name()));
+            Ok(())
+        }
+        Cmd::Amend { id, at, task } => {
+            let cmd = cr.amend_command(id, at, task.as_deref())?;
+            println!("{}", cmd);
+            Ok(())
+        }
+        Cmd::Delete { id } => {
+            let cmd = cr.delete_command(id)?;
+            println!("Deleted {}", cmd);
+            Ok(())
+        }
+        Cmd::Journal => {
+            cr.journal()?.iter().for_each(|entry| println!("{}", entry));
+            Ok(())
+        }
+        Cmd::Undo { n } => {
+            cr.undo(n)?.iter().for_each(|entry| println!("Undid {}", entry.description));
+            Ok(())
+        }
+        Cmd::Redo { n } => {
+            cr.redo(n)?.iter().for_each(|entry| println!("Redid {}", entry.description));
+            Ok(())
+        }
+        Cmd::Verify => {
+            let report = cr.verify_integrity()?;
+            if !report.is_intact() {
+                return Err(ClockError::CorruptData(report.to_string()));
+            }
+            println!("{}", report);
+            Ok(())
+        }
+        Cmd::Export { format, output: file, filter } => {
+            let cmds = cr.commands(&filter.command_filter())?;
+            let writer: Box<dyn io::Write> = match file {
+                Some(path) => Box::new(File::create(path)?),
+                None => Box::new(io::stdout().lock()),
+            };
+            match format {
+                Format::Json => output::write_commands_json(writer, &cmds, output::JsonFormat::Json),
+                Format::Ndjson => output::write_commands_json(writer, &cmds, output::JsonFormat::Ndjson),
+                Format::Csv => output::write_commands_csv(writer, &cmds, &output::CsvOptions::default()),
+                Format::Timeclock => output::write_timeclock(writer, &cmds, &Local),
+            }
+        }
+        Cmd::Import { inputs, format } => {
+            let mut converted = import::Converted::default();
+            for input in inputs {
+                let file = File::open(&input)?;
+                let mut from_file: import::Converted = match format {
+                    ImportFormat::Json => import::read_json(file)?.into(),
+                    ImportFormat::Timeclock => import::read_timeclock(file, &Local)?.into(),
+                    ImportFormat::Timewarrior => import::read_timewarrior(file)?,
+                    ImportFormat::Watson => import::read_watson(file)?,
+                };
+                from_file.skipped.iter_mut().for_each(|why| *why = format!("{}: {}", input, why));
+                converted.extend(from_file);
+            }
+            let report = converted.import_into(&cr)?;
+            println!("{}", report);
+            Ok(())
+        }
+    }
+}
+
+///Build the command the same way library callers do, so the same rules apply
+fn clock<S: ClockStore>(cr: &ClockRuster<S>, cmd_type: CommandType, args: ClockArgs) -> Result<(), ClockError> {
+    let at = args.at.unwrap_or_else(|| Utc::now().to_rfc3339());
+    let mut cmd = Command::new(cmd_type, parse_when(&at)?, args.task);
+    cmd.note = args.note;
+    cmd.tags = args.tag;
+    let cmd = create_command(&cmd.to_command_string())?;
+    cr.run_clock_command(&cmd)?;
+    println!("{}", cmd);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_parse_clock_in() {
+        let cli = Cli::try_parse_from(["clockrust", "--file", "./times", "in", "write docs", "--at", "2022-01-31T17:00:00Z"]).unwrap();
+        assert_eq!(cli.file.as_deref(), Some("./times"));
+        match cli.command {
+            Cmd::In(args) => {
+                assert_eq!(args.task, "write docs");
+                assert_eq!(args.at.as_deref(), Some("2022-01-31T17:00:00Z"));
+            }
+            _ => panic!("Expected the in subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_parse_report_filters() {
+        let cli = Cli::try_parse_from(["clockrust", "report", "--by", "project", "--project", "clientA", "--tag", "billable", "--tag", "client work"]).unwrap();
+        match cli.command {
+            Cmd::Report { by, filter, .. } => {
+                assert!(matches!(by, Grouping::Project));
+                assert_eq!(filter.command_filter().tags, vec!["billable".to_string(), "client work".to_string()]);
+                assert_eq!(filter.command_filter().project.as_deref(), Some("clientA"));
+            }
+            _ => panic!("Expected the report subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_bad_time_is_bad_command() {
+        let cr = ClockRuster::in_memory();
+        let args = ClockArgs { task: "write docs".to_string(), at: Some("half past".to_string()), note: None, tag: Vec::new() };
+        match clock(&cr, CommandType::ClockIn, args) {
+            Err(error) => assert_eq!(exit_code(&error), EXIT_BAD_COMMAND),
+            Ok(()) => panic!("Should not accept a bad time"),
+        }
+    }
+}
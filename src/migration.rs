@@ -0,0 +1,526 @@
+use rusqlite::{params, Connection};
+use tracing::info;
+
+use crate::command::task_hash;
+use crate::db::{ensure_project, rebuild_open_sessions, reseal_with};
+use crate::error::ClockError;
+
+///A single forward step of the storage schema
+/// The schema version is kept in PRAGMA user_version, 0 being a file we have never touched
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    apply: fn(&Connection) -> Result<(), ClockError>,
+}
+
+///Every migration, oldest first
+/// Only ever append to this list - released files depend on the existing steps
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create clock_rust_tasks and rehash tasks with the stable task hash",
+        apply: create_tasks_table,
+    },
+    Migration {
+        version: 2,
+        description: "index clock_rust_tasks by task hash and date",
+        apply: index_task_hash,
+    },
+    Migration {
+        version: 3,
+        description: "add a note to clock_rust_tasks",
+        apply: add_note_column,
+    },
+    Migration {
+        version: 4,
+        description: "create clock_rust_tags, tagging commands",
+        apply: create_tags_table,
+    },
+    Migration {
+        version: 5,
+        description: "create clock_rust_projects and file every task under its path",
+        apply: create_projects_table,
+    },
+    Migration {
+        version: 6,
+        description: "create clock_rust_journal, and rebuild clock_rust_tasks so a deleted command's id is never handed out again",
+        apply: create_journal_tables,
+    },
+    Migration {
+        version: 7,
+        description: "add chain_hash to clock_rust_tasks and seal every command into the chain",
+        apply: add_chain_hash_column,
+    },
+    Migration {
+        version: 8,
+        description: "add system to clock_rust_tasks, marking commands clockrusting put in itself",
+        apply: add_system_column,
+    },
+    Migration {
+        version: 9,
+        description: "create clock_rust_open_sessions, keeping every session still open",
+        apply: create_open_sessions_table,
+    },
+];
+
+///Version a fully migrated file is at
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+///Schema version the file is currently at
+pub fn schema_version(conn: &Connection) -> Result<i64, ClockError> {
+    Ok(conn.pragma_query_value(None, "user_version", |row| row.get(0))?)
+}
+
+///Run every migration newer than the file's schema version, each in its own transaction
+/// With dry_run the migrations are still run, to prove they work, but every one is rolled back
+/// Returns the versions that were (or would have been) applied
+pub fn migrate(conn: &Connection, dry_run: bool) -> Result<Vec<i64>, ClockError> {
+    let current = schema_version(conn)?;
+    if current > latest_version() {
+        return Err(ClockError::UnsupportedSchema { found: current, latest: latest_version() });
+    }
+
+    let mut applied = Vec::new();
+    //a dry run has to see earlier steps to check later ones, so it shares one transaction
+    let dry_run_tx = if dry_run { Some(conn.unchecked_transaction()?) } else { None };
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        info!("Migrating storage to version {}: {}", migration.version, migration.description);
+        if dry_run {
+            (migration.apply)(conn)?;
+        } else {
+            let tx = conn.unchecked_transaction()?;
+            (migration.apply)(&tx)?;
+            tx.pragma_update(None, "user_version", migration.version)?;
+            tx.commit()?;
+        }
+        applied.push(migration.version);
+    }
+    if let Some(tx) = dry_run_tx {
+        tx.rollback()?;
+    }
+    Ok(applied)
+}
+
+fn create_tasks_table(conn: &Connection) -> Result<(), ClockError> {
+    conn.execute("
+        CREATE TABLE IF NOT EXISTS clock_rust_tasks(
+            id INTEGER PRIMARY KEY ASC,
+            command TEXT,
+            task TEXT,
+            hash INTEGER,
+            cmd_date DATETIME
+        )
+    ", [])?;
+    //files from before this migration used DefaultHasher, which is not stable across Rust releases
+    let tasks: Vec<String> = {
+        let mut stmt = conn.prepare("select distinct task from clock_rust_tasks")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect::<Result<_, _>>()?
+    };
+    for task in &tasks {
+        conn.execute("update clock_rust_tasks set hash = ?1 where task = ?2", params![task_hash(task), task])?;
+    }
+    info!("Rehashed {} tasks", tasks.len());
+    Ok(())
+}
+
+fn index_task_hash(conn: &Connection) -> Result<(), ClockError> {
+    conn.execute("CREATE INDEX IF NOT EXISTS clock_rust_tasks_hash_date ON clock_rust_tasks(hash, cmd_date)", [])?;
+    Ok(())
+}
+
+fn add_note_column(conn: &Connection) -> Result<(), ClockError> {
+    conn.execute("ALTER TABLE clock_rust_tasks ADD COLUMN note TEXT", [])?;
+    Ok(())
+}
+
+fn create_tags_table(conn: &Connection) -> Result<(), ClockError> {
+    conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS clock_rust_tags(
+            command_id INTEGER NOT NULL REFERENCES clock_rust_tasks(id),
+            tag TEXT NOT NULL,
+            PRIMARY KEY (command_id, tag)
+        );
+        CREATE INDEX IF NOT EXISTS clock_rust_tags_tag ON clock_rust_tags(tag);
+    ")?;
+    Ok(())
+}
+
+fn create_projects_table(conn: &Connection) -> Result<(), ClockError> {
+    conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS clock_rust_projects(
+            id INTEGER PRIMARY KEY ASC,
+            path TEXT NOT NULL UNIQUE,
+            parent_id INTEGER REFERENCES clock_rust_projects(id)
+        );
+        ALTER TABLE clock_rust_tasks ADD COLUMN project_id INTEGER REFERENCES clock_rust_projects(id);
+        CREATE INDEX IF NOT EXISTS clock_rust_tasks_project ON clock_rust_tasks(project_id);
+    ")?;
+    let tasks: Vec<String> = {
+        let mut stmt = conn.prepare("select distinct task from clock_rust_tasks")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect::<Result<_, _>>()?
+    };
+    for task in &tasks {
+        let project_id = ensure_project(conn, task)?;
+        conn.execute("update clock_rust_tasks set project_id = ?1 where task = ?2", params![project_id, task])?;
+    }
+    info!("Filed {} tasks under projects", tasks.len());
+    Ok(())
+}
+
+fn create_journal_tables(conn: &Connection) -> Result<(), ClockError> {
+    conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS clock_rust_journal(
+            id INTEGER PRIMARY KEY ASC,
+            kind TEXT NOT NULL,
+            target INTEGER REFERENCES clock_rust_journal(id),
+            at DATETIME NOT NULL,
+            description TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS clock_rust_journal_changes(
+            id INTEGER PRIMARY KEY ASC,
+            entry_id INTEGER NOT NULL REFERENCES clock_rust_journal(id),
+            action TEXT NOT NULL,
+            command_id INTEGER NOT NULL,
+            before TEXT,
+            after TEXT
+        );
+        CREATE INDEX IF NOT EXISTS clock_rust_journal_changes_entry ON clock_rust_journal_changes(entry_id);
+    ")?;
+    //sqlite only changes a primary key to AUTOINCREMENT by building the table again,
+    //copying the rows in starts the sequence after the newest id
+    conn.execute_batch("
+        CREATE TABLE clock_rust_tasks_autoincrement(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            command TEXT,
+            task TEXT,
+            hash INTEGER,
+            cmd_date DATETIME,
+            note TEXT,
+            project_id INTEGER REFERENCES clock_rust_projects(id)
+        );
+        INSERT INTO clock_rust_tasks_autoincrement (id, command, task, hash, cmd_date, note, project_id)
+            SELECT id, command, task, hash, cmd_date, note, project_id FROM clock_rust_tasks ORDER BY id;
+        DROP TABLE clock_rust_tasks;
+        ALTER TABLE clock_rust_tasks_autoincrement RENAME TO clock_rust_tasks;
+        CREATE INDEX IF NOT EXISTS clock_rust_tasks_hash_date ON clock_rust_tasks(hash, cmd_date);
+        CREATE INDEX IF NOT EXISTS clock_rust_tasks_project ON clock_rust_tasks(project_id);
+    ")?;
+    Ok(())
+}
+
+//the task hash only finds a task's commands quickly, the chain is what shows nothing has changed
+fn add_chain_hash_column(conn: &Connection) -> Result<(), ClockError> {
+    conn.execute("ALTER TABLE clock_rust_tasks ADD COLUMN chain_hash TEXT", [])?;
+    //no command was marked as system yet
+    reseal_with(conn, "select command, task, cmd_date, note, id, tag, 0 from clock_rust_tasks
+                        LEFT JOIN clock_rust_tags ON command_id = id ", i64::MIN)
+}
+
+fn add_system_column(conn: &Connection) -> Result<(), ClockError> {
+    conn.execute("ALTER TABLE clock_rust_tasks ADD COLUMN system INTEGER NOT NULL DEFAULT 0", [])?;
+    Ok(())
+}
+
+fn create_open_sessions_table(conn: &Connection) -> Result<(), ClockError> {
+    conn.execute("
+        CREATE TABLE IF NOT EXISTS clock_rust_open_sessions(
+            hash INTEGER PRIMARY KEY,
+            task TEXT NOT NULL,
+            start DATETIME NOT NULL,
+            paused_ns INTEGER NOT NULL,
+            paused_since DATETIME,
+            notes TEXT NOT NULL,
+            tags TEXT NOT NULL
+        )
+    ", [])?;
+    rebuild_open_sessions(conn, "select command, task, cmd_date, note, id, tag, system from clock_rust_tasks
+                        LEFT JOIN clock_rust_tags ON command_id = id ")
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::{eyre::eyre, Report};
+    use crate::config;
+
+    use super::*;
+
+    const TASK: &str = "migrated task";
+    ///Links for the commands in the fixtures with a note and a tag, a change here would break every sealed file
+    const LINKS: [&str; 2] = [
+        "69c53f543e12998fc9b3a0d1384d4003be3099d169f081db4d9abc571cd286b2",
+        "4de8f1a2a591ff5ab32250ce8f8926dab8112620eb82295842befa36b50fd1ee",
+    ];
+
+    ///Schema as it was written at each released version, never generated from the migrations themselves
+    fn fixture(version: i64) -> Result<Connection, Report> {
+        let conn = Connection::open_in_memory()?;
+        match version {
+            //before we tracked versions, with DefaultHasher hashes
+            0 => {
+                conn.execute_batch("
+                    CREATE TABLE clock_rust_tasks(id INTEGER PRIMARY KEY ASC, command TEXT, task TEXT, hash INTEGER, cmd_date DATETIME);
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date) VALUES ('clock-in', 'migrated task', 1234, '2022-01-31 17:00:00+00:00');
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date) VALUES ('clock-out', 'migrated task', 1234, '2022-01-31 18:00:00+00:00');
+                ")?;
+            }
+            1 => {
+                conn.execute_batch(&format!("
+                    CREATE TABLE clock_rust_tasks(id INTEGER PRIMARY KEY ASC, command TEXT, task TEXT, hash INTEGER, cmd_date DATETIME);
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date) VALUES ('clock-in', 'migrated task', {hash}, '2022-01-31 17:00:00+00:00');
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date) VALUES ('clock-out', 'migrated task', {hash}, '2022-01-31 18:00:00+00:00');
+                    PRAGMA user_version = 1;
+                ", hash = task_hash(TASK)))?;
+            }
+            2 => {
+                conn.execute_batch(&format!("
+                    CREATE TABLE clock_rust_tasks(id INTEGER PRIMARY KEY ASC, command TEXT, task TEXT, hash INTEGER, cmd_date DATETIME);
+                    CREATE INDEX clock_rust_tasks_hash_date ON clock_rust_tasks(hash, cmd_date);
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date) VALUES ('clock-in', 'migrated task', {hash}, '2022-01-31 17:00:00+00:00');
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date) VALUES ('clock-out', 'migrated task', {hash}, '2022-01-31 18:00:00+00:00');
+                    PRAGMA user_version = 2;
+                ", hash = task_hash(TASK)))?;
+            }
+            3 => {
+                conn.execute_batch(&format!("
+                    CREATE TABLE clock_rust_tasks(id INTEGER PRIMARY KEY ASC, command TEXT, task TEXT, hash INTEGER, cmd_date DATETIME, note TEXT);
+                    CREATE INDEX clock_rust_tasks_hash_date ON clock_rust_tasks(hash, cmd_date);
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date, note) VALUES ('clock-in', 'migrated task', {hash}, '2022-01-31 17:00:00+00:00', 'a note');
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date) VALUES ('clock-out', 'migrated task', {hash}, '2022-01-31 18:00:00+00:00');
+                    PRAGMA user_version = 3;
+                ", hash = task_hash(TASK)))?;
+            }
+            4 => {
+                conn.execute_batch(&format!("
+                    CREATE TABLE clock_rust_tasks(id INTEGER PRIMARY KEY ASC, command TEXT, task TEXT, hash INTEGER, cmd_date DATETIME, note TEXT);
+                    CREATE INDEX clock_rust_tasks_hash_date ON clock_rust_tasks(hash, cmd_date);
+                    CREATE TABLE clock_rust_tags(command_id INTEGER NOT NULL REFERENCES clock_rust_tasks(id), tag TEXT NOT NULL, PRIMARY KEY (command_id, tag));
+                    CREATE INDEX clock_rust_tags_tag ON clock_rust_tags(tag);
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date, note) VALUES ('clock-in', 'migrated task', {hash}, '2022-01-31 17:00:00+00:00', 'a note');
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date) VALUES ('clock-out', 'migrated task', {hash}, '2022-01-31 18:00:00+00:00');
+                    INSERT INTO clock_rust_tags (command_id, tag) VALUES (1, 'billable');
+                    PRAGMA user_version = 4;
+                ", hash = task_hash(TASK)))?;
+            }
+            5 => {
+                conn.execute_batch(&format!("
+                    CREATE TABLE clock_rust_projects(id INTEGER PRIMARY KEY ASC, path TEXT NOT NULL UNIQUE, parent_id INTEGER REFERENCES clock_rust_projects(id));
+                    CREATE TABLE clock_rust_tasks(id INTEGER PRIMARY KEY ASC, command TEXT, task TEXT, hash INTEGER, cmd_date DATETIME, note TEXT, project_id INTEGER REFERENCES clock_rust_projects(id));
+                    CREATE INDEX clock_rust_tasks_hash_date ON clock_rust_tasks(hash, cmd_date);
+                    CREATE INDEX clock_rust_tasks_project ON clock_rust_tasks(project_id);
+                    CREATE TABLE clock_rust_tags(command_id INTEGER NOT NULL REFERENCES clock_rust_tasks(id), tag TEXT NOT NULL, PRIMARY KEY (command_id, tag));
+                    CREATE INDEX clock_rust_tags_tag ON clock_rust_tags(tag);
+                    INSERT INTO clock_rust_projects (id, path, parent_id) VALUES (1, 'migrated task', NULL);
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date, note, project_id) VALUES ('clock-in', 'migrated task', {hash}, '2022-01-31 17:00:00+00:00', 'a note', 1);
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date, project_id) VALUES ('clock-out', 'migrated task', {hash}, '2022-01-31 18:00:00+00:00', 1);
+                    INSERT INTO clock_rust_tags (command_id, tag) VALUES (1, 'billable');
+                    PRAGMA user_version = 5;
+                ", hash = task_hash(TASK)))?;
+            }
+            6 => {
+                conn.execute_batch(&format!("
+                    CREATE TABLE clock_rust_projects(id INTEGER PRIMARY KEY ASC, path TEXT NOT NULL UNIQUE, parent_id INTEGER REFERENCES clock_rust_projects(id));
+                    CREATE TABLE clock_rust_tasks(id INTEGER PRIMARY KEY AUTOINCREMENT, command TEXT, task TEXT, hash INTEGER, cmd_date DATETIME, note TEXT, project_id INTEGER REFERENCES clock_rust_projects(id));
+                    CREATE INDEX clock_rust_tasks_hash_date ON clock_rust_tasks(hash, cmd_date);
+                    CREATE INDEX clock_rust_tasks_project ON clock_rust_tasks(project_id);
+                    CREATE TABLE clock_rust_tags(command_id INTEGER NOT NULL REFERENCES clock_rust_tasks(id), tag TEXT NOT NULL, PRIMARY KEY (command_id, tag));
+                    CREATE INDEX clock_rust_tags_tag ON clock_rust_tags(tag);
+                    CREATE TABLE clock_rust_journal(id INTEGER PRIMARY KEY ASC, kind TEXT NOT NULL, target INTEGER REFERENCES clock_rust_journal(id), at DATETIME NOT NULL, description TEXT NOT NULL);
+                    CREATE TABLE clock_rust_journal_changes(id INTEGER PRIMARY KEY ASC, entry_id INTEGER NOT NULL REFERENCES clock_rust_journal(id), action TEXT NOT NULL, command_id INTEGER NOT NULL, before TEXT, after TEXT);
+                    CREATE INDEX clock_rust_journal_changes_entry ON clock_rust_journal_changes(entry_id);
+                    INSERT INTO clock_rust_projects (id, path, parent_id) VALUES (1, 'migrated task', NULL);
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date, note, project_id) VALUES ('clock-in', 'migrated task', {hash}, '2022-01-31 17:00:00+00:00', 'a note', 1);
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date, project_id) VALUES ('clock-out', 'migrated task', {hash}, '2022-01-31 18:00:00+00:00', 1);
+                    INSERT INTO clock_rust_tags (command_id, tag) VALUES (1, 'billable');
+                    INSERT INTO clock_rust_journal (kind, at, description) VALUES ('record', '2022-02-01 09:00:00+00:00', 'amend #2');
+                    INSERT INTO clock_rust_journal_changes (entry_id, action, command_id, before, after) VALUES (1, 'amend', 2,
+                        '{{\"id\":2,\"command\":\"clock-out\",\"cmd_datetime\":\"2022-01-31T19:00:00Z\",\"task\":\"migrated task\"}}',
+                        '{{\"id\":2,\"command\":\"clock-out\",\"cmd_datetime\":\"2022-01-31T18:00:00Z\",\"task\":\"migrated task\"}}');
+                    PRAGMA user_version = 6;
+                ", hash = task_hash(TASK)))?;
+            }
+            7 => {
+                conn.execute_batch(&format!("
+                    CREATE TABLE clock_rust_projects(id INTEGER PRIMARY KEY ASC, path TEXT NOT NULL UNIQUE, parent_id INTEGER REFERENCES clock_rust_projects(id));
+                    CREATE TABLE clock_rust_tasks(id INTEGER PRIMARY KEY AUTOINCREMENT, command TEXT, task TEXT, hash INTEGER, cmd_date DATETIME, note TEXT, project_id INTEGER REFERENCES clock_rust_projects(id), chain_hash TEXT);
+                    CREATE INDEX clock_rust_tasks_hash_date ON clock_rust_tasks(hash, cmd_date);
+                    CREATE INDEX clock_rust_tasks_project ON clock_rust_tasks(project_id);
+                    CREATE TABLE clock_rust_tags(command_id INTEGER NOT NULL REFERENCES clock_rust_tasks(id), tag TEXT NOT NULL, PRIMARY KEY (command_id, tag));
+                    CREATE INDEX clock_rust_tags_tag ON clock_rust_tags(tag);
+                    CREATE TABLE clock_rust_journal(id INTEGER PRIMARY KEY ASC, kind TEXT NOT NULL, target INTEGER REFERENCES clock_rust_journal(id), at DATETIME NOT NULL, description TEXT NOT NULL);
+                    CREATE TABLE clock_rust_journal_changes(id INTEGER PRIMARY KEY ASC, entry_id INTEGER NOT NULL REFERENCES clock_rust_journal(id), action TEXT NOT NULL, command_id INTEGER NOT NULL, before TEXT, after TEXT);
+                    CREATE INDEX clock_rust_journal_changes_entry ON clock_rust_journal_changes(entry_id);
+                    INSERT INTO clock_rust_projects (id, path, parent_id) VALUES (1, 'migrated task', NULL);
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date, note, project_id, chain_hash) VALUES ('clock-in', 'migrated task', {hash}, '2022-01-31 17:00:00+00:00', 'a note', 1, '{first}');
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date, project_id, chain_hash) VALUES ('clock-out', 'migrated task', {hash}, '2022-01-31 18:00:00+00:00', 1, '{second}');
+                    INSERT INTO clock_rust_tags (command_id, tag) VALUES (1, 'billable');
+                    INSERT INTO clock_rust_journal (kind, at, description) VALUES ('record', '2022-02-01 09:00:00+00:00', 'amend #2');
+                    INSERT INTO clock_rust_journal_changes (entry_id, action, command_id, before, after) VALUES (1, 'amend', 2,
+                        '{{\"id\":2,\"command\":\"clock-out\",\"cmd_datetime\":\"2022-01-31T19:00:00Z\",\"task\":\"migrated task\"}}',
+                        '{{\"id\":2,\"command\":\"clock-out\",\"cmd_datetime\":\"2022-01-31T18:00:00Z\",\"task\":\"migrated task\"}}');
+                    PRAGMA user_version = 7;
+                ", hash = task_hash(TASK), first = LINKS[0], second = LINKS[1]))?;
+            }
+            8 => {
+                conn.execute_batch(&format!("
+                    CREATE TABLE clock_rust_projects(id INTEGER PRIMARY KEY ASC, path TEXT NOT NULL UNIQUE, parent_id INTEGER REFERENCES clock_rust_projects(id));
+                    CREATE TABLE clock_rust_tasks(id INTEGER PRIMARY KEY AUTOINCREMENT, command TEXT, task TEXT, hash INTEGER, cmd_date DATETIME, note TEXT, project_id INTEGER REFERENCES clock_rust_projects(id), chain_hash TEXT, system INTEGER NOT NULL DEFAULT 0);
+                    CREATE INDEX clock_rust_tasks_hash_date ON clock_rust_tasks(hash, cmd_date);
+                    CREATE INDEX clock_rust_tasks_project ON clock_rust_tasks(project_id);
+                    CREATE TABLE clock_rust_tags(command_id INTEGER NOT NULL REFERENCES clock_rust_tasks(id), tag TEXT NOT NULL, PRIMARY KEY (command_id, tag));
+                    CREATE INDEX clock_rust_tags_tag ON clock_rust_tags(tag);
+                    CREATE TABLE clock_rust_journal(id INTEGER PRIMARY KEY ASC, kind TEXT NOT NULL, target INTEGER REFERENCES clock_rust_journal(id), at DATETIME NOT NULL, description TEXT NOT NULL);
+                    CREATE TABLE clock_rust_journal_changes(id INTEGER PRIMARY KEY ASC, entry_id INTEGER NOT NULL REFERENCES clock_rust_journal(id), action TEXT NOT NULL, command_id INTEGER NOT NULL, before TEXT, after TEXT);
+                    CREATE INDEX clock_rust_journal_changes_entry ON clock_rust_journal_changes(entry_id);
+                    INSERT INTO clock_rust_projects (id, path, parent_id) VALUES (1, 'migrated task', NULL);
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date, note, project_id, chain_hash) VALUES ('clock-in', 'migrated task', {hash}, '2022-01-31 17:00:00+00:00', 'a note', 1, '{first}');
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date, project_id, chain_hash) VALUES ('clock-out', 'migrated task', {hash}, '2022-01-31 18:00:00+00:00', 1, '{second}');
+                    INSERT INTO clock_rust_tags (command_id, tag) VALUES (1, 'billable');
+                    INSERT INTO clock_rust_journal (kind, at, description) VALUES ('record', '2022-02-01 09:00:00+00:00', 'amend #2');
+                    INSERT INTO clock_rust_journal_changes (entry_id, action, command_id, before, after) VALUES (1, 'amend', 2,
+                        '{{\"id\":2,\"command\":\"clock-out\",\"cmd_datetime\":\"2022-01-31T19:00:00Z\",\"task\":\"migrated task\"}}',
+                        '{{\"id\":2,\"command\":\"clock-out\",\"cmd_datetime\":\"2022-01-31T18:00:00Z\",\"task\":\"migrated task\"}}');
+                    PRAGMA user_version = 8;
+                ", hash = task_hash(TASK), first = LINKS[0], second = LINKS[1]))?;
+            }
+            9 => {
+                conn.execute_batch(&format!("
+                    CREATE TABLE clock_rust_projects(id INTEGER PRIMARY KEY ASC, path TEXT NOT NULL UNIQUE, parent_id INTEGER REFERENCES clock_rust_projects(id));
+                    CREATE TABLE clock_rust_tasks(id INTEGER PRIMARY KEY AUTOINCREMENT, command TEXT, task TEXT, hash INTEGER, cmd_date DATETIME, note TEXT, project_id INTEGER REFERENCES clock_rust_projects(id), chain_hash TEXT, system INTEGER NOT NULL DEFAULT 0);
+                    CREATE INDEX clock_rust_tasks_hash_date ON clock_rust_tasks(hash, cmd_date);
+                    CREATE INDEX clock_rust_tasks_project ON clock_rust_tasks(project_id);
+                    CREATE TABLE clock_rust_tags(command_id INTEGER NOT NULL REFERENCES clock_rust_tasks(id), tag TEXT NOT NULL, PRIMARY KEY (command_id, tag));
+                    CREATE INDEX clock_rust_tags_tag ON clock_rust_tags(tag);
+                    CREATE TABLE clock_rust_journal(id INTEGER PRIMARY KEY ASC, kind TEXT NOT NULL, target INTEGER REFERENCES clock_rust_journal(id), at DATETIME NOT NULL, description TEXT NOT NULL);
+                    CREATE TABLE clock_rust_journal_changes(id INTEGER PRIMARY KEY ASC, entry_id INTEGER NOT NULL REFERENCES clock_rust_journal(id), action TEXT NOT NULL, command_id INTEGER NOT NULL, before TEXT, after TEXT);
+                    CREATE INDEX clock_rust_journal_changes_entry ON clock_rust_journal_changes(entry_id);
+                    INSERT INTO clock_rust_projects (id, path, parent_id) VALUES (1, 'migrated task', NULL);
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date, note, project_id, chain_hash) VALUES ('clock-in', 'migrated task', {hash}, '2022-01-31 17:00:00+00:00', 'a note', 1, '{first}');
+                    INSERT INTO clock_rust_tasks (command, task, hash, cmd_date, project_id, chain_hash) VALUES ('clock-out', 'migrated task', {hash}, '2022-01-31 18:00:00+00:00', 1, '{second}');
+                    INSERT INTO clock_rust_tags (command_id, tag) VALUES (1, 'billable');
+                    INSERT INTO clock_rust_journal (kind, at, description) VALUES ('record', '2022-02-01 09:00:00+00:00', 'amend #2');
+                    INSERT INTO clock_rust_journal_changes (entry_id, action, command_id, before, after) VALUES (1, 'amend', 2,
+                        '{{\"id\":2,\"command\":\"clock-out\",\"cmd_datetime\":\"2022-01-31T19:00:00Z\",\"task\":\"migrated task\"}}',
+                        '{{\"id\":2,\"command\":\"clock-out\",\"cmd_datetime\":\"2022-01-31T18:00:00Z\",\"task\":\"migrated task\"}}');
+                    CREATE TABLE clock_rust_open_sessions(hash INTEGER PRIMARY KEY, task TEXT NOT NULL, start DATETIME NOT NULL, paused_ns INTEGER NOT NULL, paused_since DATETIME, notes TEXT NOT NULL, tags TEXT NOT NULL);
+                    PRAGMA user_version = 9;
+                ", hash = task_hash(TASK), first = LINKS[0], second = LINKS[1]))?;
+            }
+            _ => return Err(eyre!("No fixture for version {}", version)),
+        }
+        Ok(conn)
+    }
+
+    #[test]
+    fn test_migrate_empty_file() -> Result<(), Report> {
+        config::setup_test_logging();
+        let conn = Connection::open_in_memory()?;
+        let applied = migrate(&conn, false)?;
+        assert_eq!(applied.len(), MIGRATIONS.len());
+        assert_eq!(schema_version(&conn)?, latest_version());
+        //and running it again does nothing
+        assert!(migrate(&conn, false)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_upgrade_every_version() -> Result<(), Report> {
+        config::setup_test_logging();
+        for version in 0..=latest_version() {
+            let conn = fixture(version)?;
+            let applied = migrate(&conn, false)?;
+            assert_eq!(applied, ((version + 1)..=latest_version()).collect::<Vec<_>>());
+            assert_eq!(schema_version(&conn)?, latest_version());
+
+            let count: i64 = conn.query_row("select count(*) from clock_rust_tasks where hash = ?1", [task_hash(TASK)], |row| row.get(0))?;
+            assert_eq!(count, 2, "rows lost or not rehashed upgrading from version {}", version);
+            let indexed: i64 = conn.query_row("select count(*) from sqlite_master where type = 'index' and name = 'clock_rust_tasks_hash_date'", [], |row| row.get(0))?;
+            assert_eq!(indexed, 1);
+            conn.prepare("select note from clock_rust_tasks")?;
+            conn.prepare("select command_id, tag from clock_rust_tags")?;
+            conn.prepare("select system from clock_rust_tasks")?;
+            conn.prepare("select hash from clock_rust_open_sessions")?;
+            let autoincrement: i64 = conn.query_row("select count(*) from sqlite_master where name = 'clock_rust_tasks' and sql like '%AUTOINCREMENT%'", [], |row| row.get(0))?;
+            assert_eq!(autoincrement, 1);
+            let filed: i64 = conn.query_row("select count(*) from clock_rust_tasks join clock_rust_projects p on project_id = p.id where path = ?1", [TASK], |row| row.get(0))?;
+            assert_eq!(filed, 2, "tasks not filed under their project upgrading from version {}", version);
+            let journaled: i64 = conn.query_row("select count(*) from clock_rust_journal join clock_rust_journal_changes c on entry_id = clock_rust_journal.id", [], |row| row.get(0))?;
+            assert_eq!(journaled, if version >= 6 { 1 } else { 0 }, "journal entries lost upgrading from version {}", version);
+            let mut stmt = conn.prepare("select chain_hash from clock_rust_tasks ORDER BY id")?;
+            let links: Vec<Option<String>> = stmt.query_map([], |row| row.get(0))?.collect::<Result<_, _>>()?;
+            assert!(links.iter().all(Option::is_some), "commands not sealed upgrading from version {}", version);
+            if version >= 4 {
+                assert_eq!(links, LINKS.map(|link| Some(link.to_string())), "commands sealed differently upgrading from version {}", version);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_projects_filed() -> Result<(), Report> {
+        config::setup_test_logging();
+        let conn = fixture(4)?;
+        conn.execute("INSERT INTO clock_rust_tasks (command, task, hash, cmd_date) VALUES ('clock-in', 'clientA / website/login-bug', 1, '2022-01-31 19:00:00+00:00')", [])?;
+        migrate(&conn, false)?;
+        let mut stmt = conn.prepare("select p.path, parent.path from clock_rust_projects p left join clock_rust_projects parent on p.parent_id = parent.id order by p.path")?;
+        let projects: Vec<(String, Option<String>)> = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(projects, vec![
+            ("clientA".to_string(), None),
+            ("clientA/website".to_string(), Some("clientA".to_string())),
+            ("clientA/website/login-bug".to_string(), Some("clientA/website".to_string())),
+            (TASK.to_string(), None),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_sessions_kept() -> Result<(), Report> {
+        config::setup_test_logging();
+        let conn = fixture(8)?;
+        conn.execute("INSERT INTO clock_rust_tasks (command, task, hash, cmd_date, note) VALUES ('clock-in', 'forgotten', 1, '2022-01-31 19:00:00+00:00', 'left running')", [])?;
+        migrate(&conn, false)?;
+        let open: Vec<(String, String)> = conn.prepare("select task, notes from clock_rust_open_sessions")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(open, vec![("forgotten".to_string(), r#"["left running"]"#.to_string())]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_deleted_ids_not_reused() -> Result<(), Report> {
+        config::setup_test_logging();
+        let conn = fixture(5)?;
+        migrate(&conn, false)?;
+        let insert = || conn.execute("INSERT INTO clock_rust_tasks (command, task, hash, cmd_date) VALUES ('clock-in', 'new', 2, '2022-02-01 09:00:00+00:00')", [])
+            .map(|_| conn.last_insert_rowid());
+        let newest = insert()?;
+        conn.execute("DELETE FROM clock_rust_tasks WHERE id = ?", [newest])?;
+        assert_eq!(newest, 3);
+        assert_eq!(insert()?, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run_changes_nothing() -> Result<(), Report> {
+        config::setup_test_logging();
+        let conn = fixture(0)?;
+        let applied = migrate(&conn, true)?;
+        assert_eq!(applied.len(), MIGRATIONS.len());
+        assert_eq!(schema_version(&conn)?, 0);
+        let count: i64 = conn.query_row("select count(*) from clock_rust_tasks where hash = 1234", [], |row| row.get(0))?;
+        assert_eq!(count, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuse_newer_file() -> Result<(), Report> {
+        let conn = Connection::open_in_memory()?;
+        conn.pragma_update(None, "user_version", latest_version() + 1)?;
+        assert!(matches!(migrate(&conn, false), Err(ClockError::UnsupportedSchema { .. })));
+        Ok(())
+    }
+}
@@ -1,10 +1,14 @@
 use std::fs;
-use color_eyre::Report;
+use std::io::Write;
+use std::fmt::Display;
+use chrono::{DateTime, Duration, Local, TimeZone, Utc};
 use comfy_table::{Cell, Table};
-use crate::command::Command;
+use crate::command::{Command, CommandType};
+use crate::error::ClockError;
+use crate::session::{Session, SessionList};
 
 ///Write out a neat little file with our time tracking report
-pub fn write_tracking_report(file_path: &str, cmd_list:&Vec<Command>) -> Result<(), Report> {
+pub fn write_tracking_report(file_path: &str, cmd_list:&[Command]) -> Result<(), ClockError> {
     // let mut file_contents = String::new();
     //need to think through this here
     // let mut curr_task_start: BTreeMap<String, String> = BTreeMap::new();
@@ -12,7 +16,7 @@ pub fn write_tracking_report(file_path: &str, cmd_list:&Vec<Command>) -> Result<
     //when there is a new clock-in command for a task, with a different time, we put it in the map
     let mut table = Table::new();
     // self.task, self.command, self.cmd_datetime.to_rfc3339()
-    table.set_header(vec!["Task", "Command", "DateTime"]);
+    table.set_header(vec!["Task", "Command", "DateTime", "Note"]);
     cmd_list.iter()
             .for_each(|cmd| {
                 // if cmd.task
@@ -20,7 +24,8 @@ pub fn write_tracking_report(file_path: &str, cmd_list:&Vec<Command>) -> Result<
                 table.add_row(vec![
                     Cell::new(&cmd.task),
                     Cell::new(&cmd.command),
-                    Cell::new(&cmd.cmd_datetime.to_rfc3339())
+                    Cell::new(cmd.cmd_datetime.to_rfc3339()),
+                    Cell::new(cmd.note.as_deref().unwrap_or("")),
                 ]);
                 // file_contents.push_str(&cmd.to_string());
                 // file_contents.push('\n');
@@ -31,43 +36,519 @@ pub fn write_tracking_report(file_path: &str, cmd_list:&Vec<Command>) -> Result<
     Ok(())
 }
 
+///Format a duration as hours, minutes and seconds, eg 27:03:09
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.num_seconds();
+    let sign = if secs < 0 { "-" } else { "" };
+    let secs = secs.abs();
+    format!("{}{:02}:{:02}:{:02}", sign, secs / 3600, (secs % 3600) / 60, secs % 60)
+}
+
+///Build the per-task summary table
+/// Sessions still open are counted up to the time the sessions were built and flagged in the Open column
+/// Sessions that need reviewing, see Session::needs_review, are listed after it
+pub fn summary_report(sessions: &SessionList) -> String {
+    let mut table = Table::new();
+    table.set_header(vec!["Task", "Total", "Sessions", "First activity", "Last activity", "Open", "Notes"]);
+    let totals = sessions.task_totals();
+    totals.iter()
+        .for_each(|total| {
+            let open = match total.open_since {
+                Some(since) => format!("since {}", since.to_rfc3339()),
+                None => String::new(),
+            };
+            table.add_row(vec![
+                Cell::new(&total.task),
+                Cell::new(format_duration(total.total)),
+                Cell::new(total.session_count),
+                Cell::new(total.first_activity.to_rfc3339()),
+                Cell::new(total.last_activity.to_rfc3339()),
+                Cell::new(open),
+                Cell::new(total.notes.join("\n")),
+            ]);
+        });
+    table.add_row(vec![
+        Cell::new("TOTAL"),
+        Cell::new(format_duration(sessions.total_duration())),
+        Cell::new(sessions.sessions.len()),
+        Cell::new(""),
+        Cell::new(""),
+        Cell::new(sessions.open_sessions().count()),
+        Cell::new(""),
+    ]);
+
+    let mut report = table.to_string();
+    if sessions.open_sessions().next().is_some() {
+        report.push_str(&format!("\nOpen sessions are counted up to {}\n", sessions.as_of.to_rfc3339()));
+    }
+    if sessions.needing_review().next().is_some() {
+        report.push_str("\nSessions that need reviewing:\n");
+        sessions.needing_review()
+            .for_each(|session| report.push_str(&format!("  {} {}\n", session, review_reason(session))));
+    }
+    if !sessions.anomalies.is_empty() {
+        report.push_str("\nCommands that could not be paired:\n");
+        sessions.anomalies.iter()
+            .for_each(|anomaly| report.push_str(&format!("  {}\n", anomaly)));
+    }
+    report
+}
+
+///Why the session needs reviewing, eg `(stale since 2022-01-31T21:00:00+00:00)`, empty if it doesn't
+pub fn review_reason(session: &Session) -> String {
+    match (session.stale_since, session.auto_closed) {
+        (_, true) => "(closed automatically)".to_string(),
+        (Some(since), false) => format!("(stale since {})", since.to_rfc3339()),
+        (None, false) => String::new(),
+    }
+}
+
+///Write out the per-task summary of time spent
+pub fn write_summary_report(file_path: &str, sessions: &SessionList) -> Result<(), ClockError> {
+    fs::write(file_path, summary_report(sessions))?;
+    Ok(())
+}
+
+///Build the per-tag summary table, untagged sessions last
+/// A session with several tags counts towards each of them, so the rows can add up to more than the total
+pub fn tag_report(sessions: &SessionList) -> String {
+    let mut table = Table::new();
+    table.set_header(vec!["Tag", "Total", "Sessions"]);
+    sessions.tag_totals().iter()
+        .for_each(|total| {
+            table.add_row(vec![
+                Cell::new(total.tag.as_deref().unwrap_or("(untagged)")),
+                Cell::new(format_duration(total.total)),
+                Cell::new(total.session_count),
+            ]);
+        });
+    table.add_row(vec![
+        Cell::new("TOTAL"),
+        Cell::new(format_duration(sessions.total_duration())),
+        Cell::new(sessions.sessions.len()),
+    ]);
+
+    let mut report = table.to_string();
+    if sessions.sessions.iter().any(|session| session.tags.len() > 1) {
+        report.push_str("\nSessions with several tags are counted under each of them\n");
+    }
+    if sessions.open_sessions().next().is_some() {
+        report.push_str(&format!("\nOpen sessions are counted up to {}\n", sessions.as_of.to_rfc3339()));
+    }
+    report
+}
+
+///Write out the per-tag summary of time spent
+pub fn write_tag_report(file_path: &str, sessions: &SessionList) -> Result<(), ClockError> {
+    fs::write(file_path, tag_report(sessions))?;
+    Ok(())
+}
+
+///Build the project tree table, each project indented under the one above it
+/// Total includes everything below a project, Own only the time on the project itself
+pub fn project_report(sessions: &SessionList) -> String {
+    let mut table = Table::new();
+    table.set_header(vec!["Project", "Total", "Own", "Sessions"]);
+    sessions.project_totals().iter()
+        .for_each(|total| {
+            table.add_row(vec![
+                Cell::new(format!("{}{}", "  ".repeat(total.project.depth()), total.project.name())),
+                Cell::new(format_duration(total.total)),
+                Cell::new(format_duration(total.own)),
+                Cell::new(total.session_count),
+            ]);
+        });
+    table.add_row(vec![
+        Cell::new("TOTAL"),
+        Cell::new(format_duration(sessions.total_duration())),
+        Cell::new(""),
+        Cell::new(sessions.sessions.len()),
+    ]);
+
+    let mut report = table.to_string();
+    if sessions.open_sessions().next().is_some() {
+        report.push_str(&format!("\nOpen sessions are counted up to {}\n", sessions.as_of.to_rfc3339()));
+    }
+    report
+}
+
+///Write out the project tree of time spent
+pub fn write_project_report(file_path: &str, sessions: &SessionList) -> Result<(), ClockError> {
+    fs::write(file_path, project_report(sessions))?;
+    Ok(())
+}
+
+///How times are written in csv exports
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimeFormat {
+    ///eg 2022-01-31T17:00:00+00:00
+    #[default]
+    Rfc3339,
+    ///Local time without the offset, eg 2022-01-31 19:00:00, which spreadsheets read as a date
+    Local,
+    ///Seconds since 1970-01-01 UTC
+    Epoch,
+}
+
+impl TimeFormat {
+    pub fn format(&self, at: &DateTime<Utc>) -> String {
+        match self {
+            TimeFormat::Rfc3339 => at.to_rfc3339(),
+            TimeFormat::Local => at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+            TimeFormat::Epoch => at.timestamp().to_string(),
+        }
+    }
+}
+
+///How durations are written in csv exports
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DurationUnit {
+    ///Whole seconds
+    #[default]
+    Seconds,
+    ///Minutes, to two decimal places
+    Minutes,
+    ///Hours, to two decimal places
+    Hours,
+    ///HH:MM:SS, as in the reports
+    Clock,
+}
+
+impl DurationUnit {
+    pub fn format(&self, duration: Duration) -> String {
+        let secs = duration.num_seconds();
+        match self {
+            DurationUnit::Seconds => secs.to_string(),
+            DurationUnit::Minutes => format!("{:.2}", secs as f64 / 60.0),
+            DurationUnit::Hours => format!("{:.2}", secs as f64 / 3600.0),
+            DurationUnit::Clock => format_duration(duration),
+        }
+    }
+}
+
+///Layout of a csv export
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    ///Start with a row of column names
+    pub header: bool,
+    pub time_format: TimeFormat,
+    pub duration_unit: DurationUnit,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            header: true,
+            time_format: TimeFormat::default(),
+            duration_unit: DurationUnit::default(),
+        }
+    }
+}
+
+impl CsvOptions {
+    fn writer<W: Write>(&self, writer: W) -> csv::Writer<W> {
+        csv::WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .has_headers(false)
+            .from_writer(writer)
+    }
+}
+
+///Write commands as csv, one row per command: task, command, time, note, tags
+/// Tags are joined with "; "
+pub fn write_commands_csv<W: Write>(writer: W, cmd_list: &[Command], options: &CsvOptions) -> Result<(), ClockError> {
+    let mut csv = options.writer(writer);
+    if options.header {
+        csv.write_record(["task", "command", "time", "note", "tags"])?;
+    }
+    for cmd in cmd_list {
+        csv.write_record([
+            cmd.task.as_str(),
+            &cmd.command.to_string(),
+            &options.time_format.format(&cmd.cmd_datetime),
+            cmd.note.as_deref().unwrap_or(""),
+            &cmd.tags.join("; "),
+        ])?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+///Write sessions as csv, one row per session: task, start, end, duration, paused, notes, tags
+/// A session's notes, and its tags, are joined with "; "
+/// Open sessions have no end, and are measured up to when the sessions were built
+pub fn write_sessions_csv<W: Write>(writer: W, sessions: &SessionList, options: &CsvOptions) -> Result<(), ClockError> {
+    let mut csv = options.writer(writer);
+    if options.header {
+        csv.write_record(["task", "start", "end", "duration", "paused", "notes", "tags"])?;
+    }
+    for session in &sessions.sessions {
+        csv.write_record([
+            session.task.as_str(),
+            &options.time_format.format(&session.start),
+            &session.end.map(|end| options.time_format.format(&end)).unwrap_or_default(),
+            &options.duration_unit.format(session.duration),
+            &options.duration_unit.format(session.paused),
+            &session.notes.join("; "),
+            &session.tags.join("; "),
+        ])?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+///How commands are laid out in a json export
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JsonFormat {
+    ///A single array, pretty printed
+    #[default]
+    Json,
+    ///One command per line, for appending and streaming
+    Ndjson,
+}
+
+///Write commands as json, in the form import::read_json reads back
+pub fn write_commands_json<W: Write>(mut writer: W, cmd_list: &[Command], format: JsonFormat) -> Result<(), ClockError> {
+    match format {
+        JsonFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, cmd_list)?;
+            writeln!(writer)?;
+        }
+        JsonFormat::Ndjson => {
+            for cmd in cmd_list {
+                serde_json::to_writer(&mut writer, cmd)?;
+                writeln!(writer)?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+///Starts the comments that carry what timeclock itself can't, see write_timeclock
+pub(crate) const TIMECLOCK_MARKER: &str = "; clockrust";
+
+///Write commands in ledger/hledger timeclock format, oldest first
+/// eg `i 2022-01-31 09:00:00 write docs` and `o 2022-01-31 17:00:00 write docs`
+/// Timeclock times have no offset, they are written in tz
+/// Tasks become accounts and a clock-in's note its description
+/// Timeclock only has clock-ins and clock-outs, so anything else gets a `; clockrust` comment on the line before:
+/// pauses go out as clock-outs and resumes as clock-ins, tasks that are not valid account names and notes
+/// that can't be descriptions are written in full, tags as a json list, and note commands are written whole as json
+/// hledger skips comments, import::read_timeclock uses them to restore the commands
+pub fn write_timeclock<W: Write, Tz: TimeZone>(mut writer: W, cmd_list: &[Command], tz: &Tz) -> Result<(), ClockError>
+    where Tz::Offset: Display {
+    let mut ordered: Vec<&Command> = cmd_list.iter().collect();
+    ordered.sort_by_key(|cmd| cmd.cmd_datetime);
+    for cmd in ordered {
+        let code = match cmd.command {
+            CommandType::ClockIn => "i",
+            CommandType::ClockOut => "o",
+            CommandType::Pause | CommandType::Resume | CommandType::Switch => {
+                writeln!(writer, "{} {}", TIMECLOCK_MARKER, cmd.command)?;
+                if cmd.command == CommandType::Pause { "o" } else { "i" }
+            }
+            //there is no timeclock line to hang it on
+            CommandType::Note => {
+                writeln!(writer, "{} note {}", TIMECLOCK_MARKER, serde_json::to_string(cmd)?)?;
+                continue;
+            }
+        };
+        let account = timeclock_account(&cmd.task);
+        if account != cmd.task {
+            writeln!(writer, "{} task {}", TIMECLOCK_MARKER, serde_json::to_string(&cmd.task)?)?;
+        }
+        if !cmd.tags.is_empty() {
+            writeln!(writer, "{} tags {}", TIMECLOCK_MARKER, serde_json::to_string(&cmd.tags)?)?;
+        }
+        let mut line = format!("{} {} {}", code, cmd.cmd_datetime.with_timezone(tz).format("%Y-%m-%d %H:%M:%S%.f"), account);
+        match &cmd.note {
+            Some(note) if code == "i" && is_timeclock_description(note) => line.push_str(&format!("  {}", note)),
+            Some(note) => writeln!(writer, "{} text {}", TIMECLOCK_MARKER, serde_json::to_string(note)?)?,
+            None => {}
+        }
+        writeln!(writer, "{}", line)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+///Task as an account name, which can't have runs of whitespace - two spaces start the description
+fn timeclock_account(task: &str) -> String {
+    let account = task.split_whitespace().collect::<Vec<_>>().join(" ");
+    if account.is_empty() { "unnamed".to_string() } else { account }
+}
+
+///Would the note read back the same as a description
+fn is_timeclock_description(note: &str) -> bool {
+    !note.is_empty() && note.trim() == note && !note.contains(['\n', '\r'])
+}
+
+#[cfg(test)]
 mod tests {
-    use rusqlite::Connection;
+    use color_eyre::Report;
     use tracing::info;
     use crate::command::CommandType;
     use crate::config;
     use crate::db::ClockRuster;
+    use crate::db::tests::{create_test_cmd, TEST_TASK, TEST_TASK_2};
+    use crate::policy::StalePolicy;
     use super::*;
 
     # [test]
     fn test_write_tracking_report() -> Result<(), Report>{
         config::setup_test_logging();
-        let cr = ClockRuster::init(crate::db::tests::TEST_DB_STRING);
-        if let Ok(conn) = Connection::open(cr.connection_string()){
-            cr.ensure_storage_exists(&conn)?;
-            //run a clock-in and clock-out command
-            let ci = crate::db::tests::create_test_cmd( CommandType::ClockIn,
-                                                        crate::db::tests::TEST_TASK,
-                                                        "2022-01-31 17:00:28.974008356+00:00");
-            let co = crate::db::tests::create_test_cmd( CommandType::ClockOut,
-                                                        crate::db::tests::TEST_TASK,
-                                                        "2022-01-31 18:31:28.974008356+00:00");
-            cr.run_clock_command(&ci)?;
-            cr.run_clock_command(&co)?;
-            //let's start by getting all of them
-            if let Ok(cmds) = cr.command_list(None, None, None){
-                match write_tracking_report("./test_report.txt", &cmds){
-                    Ok(()) => info!("Successfully wrote report to ./test_report.txt"),
-                    Err(why) => panic!("Could not write test report: {}", why),
-                }
-
-            }else{
-                panic!("Could not list commands")
+        let cr = ClockRuster::in_memory();
+        //run a clock-in and clock-out command
+        let ci = crate::db::tests::create_test_cmd( CommandType::ClockIn,
+                                                    crate::db::tests::TEST_TASK,
+                                                    "2022-01-31 17:00:28.974008356+00:00");
+        let co = crate::db::tests::create_test_cmd( CommandType::ClockOut,
+                                                    crate::db::tests::TEST_TASK,
+                                                    "2022-01-31 18:31:28.974008356+00:00");
+        cr.run_clock_command(&ci)?;
+        cr.run_clock_command(&co)?;
+        //let's start by getting all of them
+        if let Ok(cmds) = cr.command_list(None, None, None){
+            match write_tracking_report("./test_report.txt", &cmds){
+                Ok(()) => info!("Successfully wrote report to ./test_report.txt"),
+                Err(why) => panic!("Could not write test report: {}", why),
             }
+
+        }else{
+            panic!("Could not list commands")
         }
 
-        // std::fs::remove_file(crate::db::tests::TEST_DB_STRING).expect("could not delete test sqlite db file");
         Ok(())
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_summary_report() -> Result<(), Report>{
+        let mut cmds = vec![
+            create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, TEST_TASK, "2022-01-31 10:30:00+00:00"),
+            create_test_cmd(CommandType::ClockIn, TEST_TASK_2, "2022-01-31 11:00:00+00:00"),
+        ];
+        cmds[1].note = Some("wrapped up the summary".to_string());
+        let now = "2022-01-31 11:15:00+00:00".parse()?;
+        let sessions = SessionList::from_commands(&cmds, now);
+        let report = summary_report(&sessions);
+        assert!(report.contains("wrapped up the summary"));
+        info!("{}", report);
+        assert!(report.contains("01:30:00"));
+        assert!(report.contains("00:15:00"));
+        //grand total
+        assert!(report.contains("01:45:00"));
+        assert!(report.contains("since 2022-01-31T11:00:00+00:00"));
+        assert!(report.contains("Open sessions are counted up to 2022-01-31T11:15:00+00:00"));
+        assert!(!report.contains("need reviewing"));
+
+        let stale = StalePolicy { max_duration: Some(Duration::minutes(10)), ..Default::default() };
+        let report = summary_report(&sessions.flag_stale(&stale, &Utc));
+        assert!(report.contains("Sessions that need reviewing:\n  'Test test data' from '2022-01-31T09:00:00+00:00' to '2022-01-31T10:30:00+00:00' (stale since 2022-01-31T09:10:00+00:00)"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tag_report() -> Result<(), Report>{
+        let mut cmds = vec![
+            create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, TEST_TASK, "2022-01-31 10:30:00+00:00"),
+            create_test_cmd(CommandType::ClockIn, TEST_TASK_2, "2022-01-31 11:00:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, TEST_TASK_2, "2022-01-31 11:15:00+00:00"),
+        ];
+        cmds[0].tags = vec!["billable".to_string(), "meeting".to_string()];
+        let now = "2022-01-31 12:00:00+00:00".parse()?;
+        let report = tag_report(&SessionList::from_commands(&cmds, now));
+        info!("{}", report);
+        let row = |name: &str| report.lines().find(|line| line.contains(name)).map(String::from);
+        assert!(row("billable").is_some_and(|line| line.contains("01:30:00")));
+        assert!(row("meeting").is_some_and(|line| line.contains("01:30:00")));
+        assert!(row("(untagged)").is_some_and(|line| line.contains("00:15:00")));
+        assert!(row("TOTAL").is_some_and(|line| line.contains("01:45:00")));
+        assert!(report.contains("counted under each of them"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_report() -> Result<(), Report>{
+        let cmds = vec![
+            create_test_cmd(CommandType::ClockIn, "clientA/website/login-bug", "2022-01-31 09:00:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, "clientA/website/login-bug", "2022-01-31 10:30:00+00:00"),
+            create_test_cmd(CommandType::ClockIn, "clientA/website", "2022-01-31 11:00:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, "clientA/website", "2022-01-31 11:15:00+00:00"),
+        ];
+        let now = "2022-01-31 12:00:00+00:00".parse()?;
+        let report = project_report(&SessionList::from_commands(&cmds, now));
+        info!("{}", report);
+        assert!(report.contains("| clientA       | 01:45:00 | 00:00:00 | 2 "));
+        assert!(report.contains("|   website     | 01:45:00 | 00:15:00 | 2 "));
+        assert!(report.contains("|     login-bug | 01:30:00 | 01:30:00 | 1 "));
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::seconds(97_389)), "27:03:09");
+        assert_eq!(format_duration(Duration::zero()), "00:00:00");
+    }
+
+    #[test]
+    fn test_commands_csv() -> Result<(), Report>{
+        let mut cmds = vec![
+            create_test_cmd(CommandType::ClockIn, "task, with a comma", "2022-01-31 09:00:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, "task, with a comma", "2022-01-31 10:30:00+00:00"),
+        ];
+        cmds[1].note = Some("said \"done\"".to_string());
+        let mut out = Vec::new();
+        write_commands_csv(&mut out, &cmds, &CsvOptions::default())?;
+        assert_eq!(String::from_utf8(out)?, "task,command,time,note,tags\n\
+            \"task, with a comma\",clock-in,2022-01-31T09:00:00+00:00,,\n\
+            \"task, with a comma\",clock-out,2022-01-31T10:30:00+00:00,\"said \"\"done\"\"\",\n");
+
+        let options = CsvOptions { delimiter: b';', header: false, time_format: TimeFormat::Epoch, ..CsvOptions::default() };
+        let mut out = Vec::new();
+        write_commands_csv(&mut out, &cmds[..1], &options)?;
+        assert_eq!(String::from_utf8(out)?, "task, with a comma;clock-in;1643619600;;\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_sessions_csv() -> Result<(), Report>{
+        let mut cmds = vec![
+            create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 09:00:00+00:00"),
+            create_test_cmd(CommandType::Pause, TEST_TASK, "2022-01-31 09:30:00+00:00"),
+            create_test_cmd(CommandType::Resume, TEST_TASK, "2022-01-31 09:45:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, TEST_TASK, "2022-01-31 10:45:00+00:00"),
+            create_test_cmd(CommandType::ClockIn, TEST_TASK_2, "2022-01-31 11:00:00+00:00"),
+        ];
+        cmds[0].note = Some("start".to_string());
+        cmds[2].note = Some("back from lunch".to_string());
+        cmds[0].tags = vec!["billable".to_string()];
+        cmds[3].tags = vec!["billable".to_string(), "docs".to_string()];
+        let now = "2022-01-31 11:15:00+00:00".parse()?;
+        let sessions = SessionList::from_commands(&cmds, now);
+        let options = CsvOptions { duration_unit: DurationUnit::Hours, ..CsvOptions::default() };
+        let mut out = Vec::new();
+        write_sessions_csv(&mut out, &sessions, &options)?;
+        assert_eq!(String::from_utf8(out)?, format!("task,start,end,duration,paused,notes,tags\n\
+            {},2022-01-31T09:00:00+00:00,2022-01-31T10:45:00+00:00,1.50,0.25,start; back from lunch,billable; docs\n\
+            {},2022-01-31T11:00:00+00:00,,0.25,0.00,,\n", TEST_TASK, TEST_TASK_2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_formats() {
+        let at = "2022-01-31 09:00:00+00:00".parse().unwrap();
+        assert_eq!(TimeFormat::Rfc3339.format(&at), "2022-01-31T09:00:00+00:00");
+        assert_eq!(TimeFormat::Epoch.format(&at), "1643619600");
+        assert_eq!(TimeFormat::Local.format(&at).len(), "2022-01-31 09:00:00".len());
+        let duration = Duration::seconds(5409);
+        assert_eq!(DurationUnit::Seconds.format(duration), "5409");
+        assert_eq!(DurationUnit::Minutes.format(duration), "90.15");
+        assert_eq!(DurationUnit::Hours.format(duration), "1.50");
+        assert_eq!(DurationUnit::Clock.format(duration), "01:30:09");
+    }
+}
@@ -0,0 +1,194 @@
+use std::fmt::{Display, Formatter};
+use chrono::{DateTime, Duration, Local, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::command::{Command, CommandType};
+use crate::session::Session;
+
+///How strictly commands are checked against the tasks already open
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TrackingPolicy {
+    ///Anything goes, as it always has
+    #[default]
+    Permissive,
+    ///Any number of tasks can be open, but each command has to make sense for its own task
+    MultiTask,
+    ///Only one task can be open at a time
+    /// With auto_close, clocking in to a task clocks out of whatever else is open instead of failing
+    SingleTask { auto_close: bool },
+}
+
+///Why a command was refused under the tracking policy
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransitionError {
+    AlreadyTracking { task: String, since: DateTime<Utc> },
+    AlreadyPaused { task: String, since: DateTime<Utc> },
+    NotTracking { task: String },
+    NotPaused { task: String },
+}
+
+impl Display for TransitionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransitionError::AlreadyTracking { task, since } => write!(f, "already tracking '{}' since {}", task, local_time(since)),
+            TransitionError::AlreadyPaused { task, since } => write!(f, "'{}' is paused since {}", task, local_time(since)),
+            TransitionError::NotTracking { task } => write!(f, "not tracking '{}'", task),
+            TransitionError::NotPaused { task } => write!(f, "'{}' is not paused", task),
+        }
+    }
+}
+
+impl std::error::Error for TransitionError {}
+
+fn local_time(at: &DateTime<Utc>) -> String {
+    at.with_timezone(&Local).format("%H:%M").to_string()
+}
+
+impl TrackingPolicy {
+    ///Check the command against the sessions currently open
+    /// Returns the clock-outs that have to go in before it, which is only ever the auto-closed tasks
+    pub fn check(&self, cmd: &Command, open: &[Session]) -> Result<Vec<Command>, TransitionError> {
+        if *self == TrackingPolicy::Permissive {
+            return Ok(Vec::new());
+        }
+
+        let own = open.iter().find(|session| session.task == cmd.task);
+        match (&cmd.command, own) {
+            (CommandType::ClockIn, Some(session)) => return Err(already_open(session)),
+            (CommandType::ClockOut | CommandType::Pause | CommandType::Resume | CommandType::Note, None) => {
+                return Err(TransitionError::NotTracking { task: cmd.task.clone() })
+            }
+            (CommandType::Pause, Some(session)) if session.is_paused() => return Err(already_open(session)),
+            (CommandType::Resume, Some(session)) if !session.is_paused() => {
+                return Err(TransitionError::NotPaused { task: cmd.task.clone() })
+            }
+            _ => {}
+        }
+
+        //a switch closes everything else itself
+        let mut auto_closed = Vec::new();
+        if let (TrackingPolicy::SingleTask { auto_close }, CommandType::ClockIn) = (self, &cmd.command) {
+            for other in open.iter().filter(|session| session.task != cmd.task) {
+                if !auto_close {
+                    return Err(already_open(other));
+                }
+                auto_closed.push(Command::new(CommandType::ClockOut, cmd.cmd_datetime, other.task.clone()));
+            }
+        }
+        Ok(auto_closed)
+    }
+}
+
+///When a session has gone on too long to be real, most likely a forgotten clock-out
+/// Stale sessions are marked as needing review, and only clocked out by ClockRuster::close_stale
+/// With neither limit set, which is the default, nothing is ever stale
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StalePolicy {
+    ///Longest a session can run from its clock-in, pauses included
+    pub max_duration: Option<Duration>,
+    ///Time of day, local to wherever the policy is applied, that ends any session started before it
+    pub end_of_day: Option<NaiveTime>,
+}
+
+impl StalePolicy {
+    ///When the session goes stale under whichever limit comes first, or None if neither applies
+    /// A session started at or after the end of the day runs until the end of the next
+    pub fn stale_at<Tz: TimeZone>(&self, session: &Session, tz: &Tz) -> Option<DateTime<Utc>> {
+        //a limit too long to add on never comes
+        let by_length = self.max_duration.and_then(|max| session.start.checked_add_signed(max));
+        let by_end_of_day = self.end_of_day.and_then(|end_of_day| {
+            let start = session.start.with_timezone(tz);
+            let mut day = start.date_naive();
+            if start.time() >= end_of_day {
+                day = day.succ_opt()?;
+            }
+            tz.from_local_datetime(&day.and_time(end_of_day)).earliest().map(|at| at.with_timezone(&Utc))
+        });
+        by_length.into_iter().chain(by_end_of_day).min()
+    }
+}
+
+fn already_open(session: &Session) -> TransitionError {
+    match session.paused_since {
+        Some(since) => TransitionError::AlreadyPaused { task: session.task.clone(), since },
+        None => TransitionError::AlreadyTracking { task: session.task.clone(), since: session.start },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::parse_duration;
+    use crate::db::tests::create_test_cmd;
+    use crate::session::SessionList;
+    use chrono_tz::Europe::Berlin;
+
+    use super::*;
+
+    const TASK: &str = "policy task";
+    const OTHER_TASK: &str = "other policy task";
+
+    fn open_sessions(cmds: &[Command]) -> Vec<Session> {
+        let now = "2022-01-31 20:00:00+00:00".parse().unwrap();
+        SessionList::from_commands(cmds, now).open_sessions().cloned().collect()
+    }
+
+    #[test]
+    fn test_permissive_allows_anything() {
+        let open = open_sessions(&[create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 09:00:00+00:00")]);
+        let cmd = create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 10:00:00+00:00");
+        assert_eq!(TrackingPolicy::Permissive.check(&cmd, &open), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_multi_task_checks_own_task() {
+        let clock_in = create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 09:14:00+00:00");
+        let open = open_sessions(std::slice::from_ref(&clock_in));
+        let policy = TrackingPolicy::MultiTask;
+
+        let again = create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 10:00:00+00:00");
+        assert_eq!(policy.check(&again, &open), Err(TransitionError::AlreadyTracking { task: TASK.to_string(), since: clock_in.cmd_datetime }));
+        let other_in = create_test_cmd(CommandType::ClockIn, OTHER_TASK, "2022-01-31 10:00:00+00:00");
+        assert_eq!(policy.check(&other_in, &open), Ok(Vec::new()));
+        let other_out = create_test_cmd(CommandType::ClockOut, OTHER_TASK, "2022-01-31 10:00:00+00:00");
+        assert_eq!(policy.check(&other_out, &open), Err(TransitionError::NotTracking { task: OTHER_TASK.to_string() }));
+        let resume = create_test_cmd(CommandType::Resume, TASK, "2022-01-31 10:00:00+00:00");
+        assert_eq!(policy.check(&resume, &open), Err(TransitionError::NotPaused { task: TASK.to_string() }));
+    }
+
+    #[test]
+    fn test_single_task() {
+        let open = open_sessions(&[create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 09:14:00+00:00")]);
+        let other_in = create_test_cmd(CommandType::ClockIn, OTHER_TASK, "2022-01-31 10:00:00+00:00");
+
+        let strict = TrackingPolicy::SingleTask { auto_close: false };
+        let err = strict.check(&other_in, &open).unwrap_err();
+        assert!(matches!(err, TransitionError::AlreadyTracking { ref task, .. } if task == TASK));
+        assert!(err.to_string().starts_with("already tracking 'policy task' since "));
+
+        let auto_close = TrackingPolicy::SingleTask { auto_close: true };
+        assert_eq!(auto_close.check(&other_in, &open), Ok(vec![
+            Command::new(CommandType::ClockOut, other_in.cmd_datetime, TASK.to_string()),
+        ]));
+    }
+
+    #[test]
+    fn test_stale_at() {
+        let evening = open_sessions(&[create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 17:30:00+00:00")]);
+        let at = |policy: StalePolicy| policy.stale_at(&evening[0], &Berlin).map(|at| at.to_rfc3339());
+        assert_eq!(at(StalePolicy::default()), None);
+
+        let twelve_hours = StalePolicy { max_duration: Some(Duration::hours(12)), ..Default::default() };
+        assert_eq!(at(twelve_hours), Some("2022-02-01T05:30:00+00:00".to_string()));
+        //18:30 in Berlin, so the end of the same day
+        let end_of_day = StalePolicy { end_of_day: NaiveTime::from_hms_opt(19, 0, 0), ..Default::default() };
+        assert_eq!(at(end_of_day), Some("2022-01-31T18:00:00+00:00".to_string()));
+        let both = StalePolicy { max_duration: Some(Duration::minutes(20)), ..end_of_day };
+        assert_eq!(at(both), Some("2022-01-31T17:50:00+00:00".to_string()));
+        //started after the end of the day, so it has until the next one
+        let early = StalePolicy { end_of_day: NaiveTime::from_hms_opt(18, 0, 0), ..Default::default() };
+        assert_eq!(at(early), Some("2022-02-01T17:00:00+00:00".to_string()));
+        let forever = StalePolicy { max_duration: parse_duration("99999999d"), ..Default::default() };
+        assert_eq!(at(forever), None);
+    }
+}
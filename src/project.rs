@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+
+///Separates the levels of a path-style task, eg `clientA/website/login-bug`
+pub const SEPARATOR: char = '/';
+
+///A node in the project hierarchy, every level of every task's path is one
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Project {
+    ///Normalized path from the top, eg `clientA/website`
+    pub path: String,
+    ///Path of the project above, None at the top
+    pub parent: Option<String>,
+}
+
+impl Project {
+    ///Last level of the path, eg `website` for `clientA/website`
+    pub fn name(&self) -> &str {
+        self.path.rsplit(SEPARATOR).next().unwrap_or(&self.path)
+    }
+
+    ///Number of projects above this one
+    pub fn depth(&self) -> usize {
+        self.path.matches(SEPARATOR).count()
+    }
+}
+
+///Levels of a task's path, with whitespace around each trimmed and empty ones dropped
+/// `clientA / website//login-bug` is `["clientA", "website", "login-bug"]`, a task without a `/` is a single level
+pub fn segments(task: &str) -> Vec<&str> {
+    task.split(SEPARATOR)
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .collect()
+}
+
+///The task's path as it is kept for projects, empty if it has no levels at all
+pub fn normalize(task: &str) -> String {
+    segments(task).join(&SEPARATOR.to_string())
+}
+
+///Every project from the top down to the task itself
+/// `clientA/website/login-bug` has `clientA`, `clientA/website` and `clientA/website/login-bug`
+pub fn ancestors(task: &str) -> Vec<Project> {
+    let mut projects: Vec<Project> = Vec::new();
+    for segment in segments(task) {
+        let parent = projects.last().map(|project| project.path.clone());
+        let path = match &parent {
+            Some(parent) => format!("{}{}{}", parent, SEPARATOR, segment),
+            None => segment.to_string(),
+        };
+        projects.push(Project { path, parent });
+    }
+    projects
+}
+
+///Is the task `root` or somewhere below it
+/// Whole levels are compared, so `client` does not contain `clientA/website`, and an empty root contains everything
+pub fn in_subtree(task: &str, root: &str) -> bool {
+    let root = segments(root);
+    let task = segments(task);
+    task.len() >= root.len() && task[..root.len()] == root[..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(segments("clientA / website//login-bug "), vec!["clientA", "website", "login-bug"]);
+        assert_eq!(normalize(" write docs "), "write docs");
+        assert_eq!(normalize("/"), "");
+
+        let projects = ancestors("clientA/website/login-bug");
+        let paths: Vec<&str> = projects.iter().map(|project| project.path.as_str()).collect();
+        assert_eq!(paths, vec!["clientA", "clientA/website", "clientA/website/login-bug"]);
+        assert_eq!(projects[0].parent, None);
+        assert_eq!(projects[2].parent.as_deref(), Some("clientA/website"));
+        assert_eq!(projects[2].name(), "login-bug");
+        assert_eq!(projects[2].depth(), 2);
+    }
+
+    #[test]
+    fn test_in_subtree() {
+        assert!(in_subtree("clientA/website/login-bug", "clientA"));
+        assert!(in_subtree("clientA/website/login-bug", "clientA / website"));
+        assert!(in_subtree("clientA/website", "clientA/website"));
+        assert!(!in_subtree("clientA/website", "clientA/website/login-bug"));
+        assert!(!in_subtree("clientAB/website", "clientA"));
+        assert!(in_subtree("anything", ""));
+    }
+}
@@ -0,0 +1,725 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::command::{task_hash, Command, CommandType};
+use crate::policy::StalePolicy;
+use crate::project::{self, Project};
+
+///A span of time spent on a task, from a clock-in to its matching clock-out
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub task: String,
+    pub start: DateTime<Utc>,
+    ///None while the task is still clocked in
+    pub end: Option<DateTime<Utc>>,
+    ///Time worked from start to end, or up to the time the sessions were built if still open
+    /// Paused time is not included
+    #[serde(with = "seconds")]
+    pub duration: Duration,
+    ///Total time spent paused
+    #[serde(with = "seconds")]
+    pub paused: Duration,
+    ///When the session was paused, if it still is
+    pub paused_since: Option<DateTime<Utc>>,
+    ///Notes from the session's commands, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+    ///Tags from the session's commands, each once, in the order first seen
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    ///When the session ran past what the StalePolicy allows, if it did, see SessionList::flag_stale
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stale_since: Option<DateTime<Utc>>,
+    ///Clocked out by a system clock-out, closing it as stale, rather than by anyone
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub auto_closed: bool,
+}
+
+impl Session {
+    pub fn is_open(&self) -> bool {
+        self.end.is_none()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_since.is_some()
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    ///Stale or auto-closed, so its end is a guess someone should check
+    pub fn needs_review(&self) -> bool {
+        self.stale_since.is_some() || self.auto_closed
+    }
+}
+
+///Where a task stands after its commands so far
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TaskState {
+    ///Not clocked in
+    Idle,
+    Tracking,
+    ///Clocked in but paused - not tracking, but still open
+    Paused,
+}
+
+impl TaskState {
+    ///State after the command, or None if the command makes no sense in this state
+    pub fn apply(self, cmd_type: &CommandType) -> Option<TaskState> {
+        match (self, cmd_type) {
+            (TaskState::Idle, CommandType::ClockIn) => Some(TaskState::Tracking),
+            (TaskState::Tracking, CommandType::Pause) => Some(TaskState::Paused),
+            (TaskState::Paused, CommandType::Resume) => Some(TaskState::Tracking),
+            (TaskState::Tracking | TaskState::Paused, CommandType::ClockOut) => Some(TaskState::Idle),
+            (TaskState::Tracking | TaskState::Paused, CommandType::Note) => Some(self),
+            //whatever else it closes, a switch leaves its own task running
+            (_, CommandType::Switch) => Some(TaskState::Tracking),
+            _ => None,
+        }
+    }
+
+    ///State after a task's commands, given in time order
+    /// Commands that make no sense are skipped, except a repeated clock-in which restarts the task as in SessionList
+    pub fn replay<'a>(cmd_types: impl IntoIterator<Item = &'a CommandType>) -> TaskState {
+        cmd_types.into_iter().fold(TaskState::Idle, |state, cmd_type| {
+            match state.apply(cmd_type) {
+                Some(next) => next,
+                None if *cmd_type == CommandType::ClockIn => TaskState::Tracking,
+                None => state,
+            }
+        })
+    }
+
+    ///Clocked in, whether paused or not
+    pub fn is_open(self) -> bool {
+        self != TaskState::Idle
+    }
+}
+
+impl Display for TaskState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskState::Idle => write!(f, "idle"),
+            TaskState::Tracking => write!(f, "tracking"),
+            TaskState::Paused => write!(f, "paused"),
+        }
+    }
+}
+
+///Where an open task stands, see ClockRuster::status
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TaskStatus {
+    pub task: String,
+    ///Tracking or paused, never idle
+    pub state: TaskState,
+    ///Start of its open session
+    pub since: DateTime<Utc>,
+    ///Time worked since then, paused time not included
+    #[serde(with = "seconds")]
+    pub elapsed: Duration,
+    ///Open past what the StalePolicy allows
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub needs_review: bool,
+}
+
+impl From<&Session> for TaskStatus {
+    fn from(session: &Session) -> Self {
+        Self {
+            task: session.task.clone(),
+            state: if session.is_paused() { TaskState::Paused } else { TaskState::Tracking },
+            since: session.start,
+            elapsed: session.duration,
+            needs_review: session.needs_review(),
+        }
+    }
+}
+
+impl Display for Session {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.end {
+            Some(end) => write!(f, "'{}' from '{}' to '{}'", self.task, self.start.to_rfc3339(), end.to_rfc3339()),
+            None => write!(f, "'{}' from '{}' (still open)", self.task, self.start.to_rfc3339()),
+        }
+    }
+}
+
+///Commands that could not be paired into a session
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Anomaly {
+    ///Clock-in that was followed by another clock-in for the same task before any clock-out
+    UnmatchedClockIn(Command),
+    ///Clock-out with no open clock-in for its task
+    OrphanClockOut(Command),
+    ///Pause with nothing running for its task
+    OrphanPause(Command),
+    ///Resume with nothing paused for its task
+    OrphanResume(Command),
+    ///Note with no open session for its task
+    OrphanNote(Command),
+}
+
+impl Anomaly {
+    ///The command that could not be paired
+    pub fn command(&self) -> &Command {
+        match self {
+            Anomaly::UnmatchedClockIn(cmd)
+            | Anomaly::OrphanClockOut(cmd)
+            | Anomaly::OrphanPause(cmd)
+            | Anomaly::OrphanResume(cmd)
+            | Anomaly::OrphanNote(cmd) => cmd,
+        }
+    }
+}
+
+impl Display for Anomaly {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Anomaly::UnmatchedClockIn(cmd) => write!(f, "clock-in never clocked out: {}", cmd),
+            Anomaly::OrphanClockOut(cmd) => write!(f, "clock-out without a clock-in: {}", cmd),
+            Anomaly::OrphanPause(cmd) => write!(f, "pause without a running clock-in: {}", cmd),
+            Anomaly::OrphanResume(cmd) => write!(f, "resume without a pause: {}", cmd),
+            Anomaly::OrphanNote(cmd) => write!(f, "note without an open session: {}", cmd),
+        }
+    }
+}
+
+///Sessions paired up from a stream of commands, along with whatever could not be paired
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionList {
+    pub sessions: Vec<Session>,
+    pub anomalies: Vec<Anomaly>,
+    ///Time open sessions were measured up to
+    pub as_of: DateTime<Utc>,
+}
+
+///Time spent on a single task, summed over its sessions
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TaskTotal {
+    pub task: String,
+    #[serde(with = "seconds")]
+    pub total: Duration,
+    pub session_count: usize,
+    pub first_activity: DateTime<Utc>,
+    ///End of the latest session, or its start if it is still open
+    pub last_activity: DateTime<Utc>,
+    ///Start of the session still open on this task, if any
+    pub open_since: Option<DateTime<Utc>>,
+    ///Notes from every session, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+}
+
+///Time spent on sessions carrying a tag
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TagTotal {
+    ///None for the sessions with no tags at all
+    pub tag: Option<String>,
+    #[serde(with = "seconds")]
+    pub total: Duration,
+    pub session_count: usize,
+}
+
+///Time spent on a project, rolled up from every task below it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProjectTotal {
+    pub project: Project,
+    ///Time on the project and everything below it
+    #[serde(with = "seconds")]
+    pub total: Duration,
+    ///Time on sessions for the project itself, not counting anything below it
+    #[serde(with = "seconds")]
+    pub own: Duration,
+    ///Sessions on the project and everything below it
+    pub session_count: usize,
+}
+
+impl SessionList {
+    ///Pair each clock-in with the next clock-out for the same task
+    /// Tasks are matched by their hash, same as in storage
+    /// A switch closes every other open session and opens (or resumes) its own
+    /// A second clock-in before a clock-out replaces the first, which is reported as unmatched
+    /// Clock-ins still open at the end become open sessions, measured up to `now`
+    pub fn from_commands(cmds: &[Command], now: DateTime<Utc>) -> Self {
+        //command_list orders by task first, we want to walk time
+        let mut ordered: Vec<&Command> = cmds.iter().collect();
+        ordered.sort_by_key(|cmd| cmd.cmd_datetime);
+
+        let mut list = SessionList { as_of: now, ..Default::default() };
+        let mut open: HashMap<i64, OpenSession> = HashMap::new();
+        for cmd in ordered {
+            let hash = task_hash(&cmd.task);
+            match cmd.command {
+                CommandType::ClockIn => {
+                    if let Some(prev) = open.insert(hash, OpenSession::new(cmd)) {
+                        list.anomalies.push(Anomaly::UnmatchedClockIn(prev.clock_in.clone()));
+                    }
+                }
+                CommandType::ClockOut => match open.remove(&hash) {
+                    Some(mut session) => {
+                        session.annotate(cmd);
+                        list.sessions.push(Session { auto_closed: cmd.system, ..session.close(cmd.cmd_datetime) });
+                    }
+                    None => list.anomalies.push(Anomaly::OrphanClockOut(cmd.clone())),
+                },
+                CommandType::Pause => match open.get_mut(&hash) {
+                    Some(session) if session.paused_since.is_none() => {
+                        session.paused_since = Some(cmd.cmd_datetime);
+                        session.annotate(cmd);
+                    }
+                    _ => list.anomalies.push(Anomaly::OrphanPause(cmd.clone())),
+                },
+                CommandType::Resume => match open.get_mut(&hash) {
+                    Some(session) if session.paused_since.is_some() => {
+                        session.resume(cmd.cmd_datetime);
+                        session.annotate(cmd);
+                    }
+                    _ => list.anomalies.push(Anomaly::OrphanResume(cmd.clone())),
+                },
+                CommandType::Note => match open.get_mut(&hash) {
+                    Some(session) => session.annotate(cmd),
+                    None => list.anomalies.push(Anomaly::OrphanNote(cmd.clone())),
+                },
+                CommandType::Switch => {
+                    open.retain(|open_hash, session| {
+                        if *open_hash == hash {
+                            return true;
+                        }
+                        list.sessions.push(session.close(cmd.cmd_datetime));
+                        false
+                    });
+                    match open.get_mut(&hash) {
+                        Some(session) => {
+                            session.resume(cmd.cmd_datetime);
+                            session.annotate(cmd);
+                        }
+                        None => { open.insert(hash, OpenSession::new(cmd)); }
+                    }
+                }
+            }
+        }
+
+        //whatever is left was never clocked out
+        for session in open.into_values() {
+            list.sessions.push(session.measure(now));
+        }
+        list.sessions.sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.task.cmp(&b.task)));
+        list
+    }
+
+    ///Mark every session that ran, or is still running, past the point the policy says it went stale
+    /// The end of the day is taken in tz
+    pub fn flag_stale<Tz: TimeZone>(mut self, policy: &StalePolicy, tz: &Tz) -> Self {
+        for session in &mut self.sessions {
+            session.stale_since = policy.stale_at(session, tz)
+                .filter(|stale_at| session.end.unwrap_or(self.as_of) > *stale_at);
+        }
+        self
+    }
+
+    ///Sessions that need reviewing, see Session::needs_review
+    pub fn needing_review(&self) -> impl Iterator<Item = &Session> {
+        self.sessions.iter().filter(|s| s.needs_review())
+    }
+
+    ///Sessions that have not been clocked out yet
+    pub fn open_sessions(&self) -> impl Iterator<Item = &Session> {
+        self.sessions.iter().filter(|s| s.is_open())
+    }
+
+    ///Sum of all session durations, open sessions included
+    pub fn total_duration(&self) -> Duration {
+        self.sessions.iter().fold(Duration::zero(), |acc, s| acc + s.duration)
+    }
+
+    ///Group sessions by task, ordered by task name
+    pub fn task_totals(&self) -> Vec<TaskTotal> {
+        let mut totals: BTreeMap<&str, TaskTotal> = BTreeMap::new();
+        for session in &self.sessions {
+            let last = session.end.unwrap_or(session.start);
+            let total = totals.entry(&session.task).or_insert_with(|| TaskTotal {
+                task: session.task.clone(),
+                total: Duration::zero(),
+                session_count: 0,
+                first_activity: session.start,
+                last_activity: last,
+                open_since: None,
+                notes: Vec::new(),
+            });
+            total.total += session.duration;
+            total.session_count += 1;
+            total.first_activity = total.first_activity.min(session.start);
+            total.last_activity = total.last_activity.max(last);
+            if session.is_open() {
+                total.open_since = Some(session.start);
+            }
+            total.notes.extend(session.notes.iter().cloned());
+        }
+        totals.into_values().collect()
+    }
+
+    ///Group sessions by tag, ordered by tag, with untagged sessions last
+    /// A session with several tags counts towards each of them, so the totals can add up to more than the time tracked
+    pub fn tag_totals(&self) -> Vec<TagTotal> {
+        let mut totals: BTreeMap<&str, TagTotal> = BTreeMap::new();
+        let mut untagged = TagTotal { tag: None, total: Duration::zero(), session_count: 0 };
+        for session in &self.sessions {
+            if session.tags.is_empty() {
+                untagged.total += session.duration;
+                untagged.session_count += 1;
+            }
+            for tag in &session.tags {
+                let total = totals.entry(tag).or_insert_with(|| TagTotal {
+                    tag: Some(tag.clone()),
+                    total: Duration::zero(),
+                    session_count: 0,
+                });
+                total.total += session.duration;
+                total.session_count += 1;
+            }
+        }
+        let mut totals: Vec<TagTotal> = totals.into_values().collect();
+        if untagged.session_count > 0 {
+            totals.push(untagged);
+        }
+        totals
+    }
+
+    ///Roll session time up the project hierarchy, eg `clientA/website/login-bug` counts towards
+    /// `clientA/website` and `clientA` as well as itself
+    /// Ordered so that each project comes straight after the one above it, ready to print as a tree
+    /// Sessions on a task with no path at all are left out
+    pub fn project_totals(&self) -> Vec<ProjectTotal> {
+        let mut totals: BTreeMap<Vec<String>, ProjectTotal> = BTreeMap::new();
+        for session in &self.sessions {
+            let ancestors = project::ancestors(&session.task);
+            let levels = ancestors.len();
+            for (level, project) in ancestors.into_iter().enumerate() {
+                let key = project::segments(&project.path).into_iter().map(String::from).collect();
+                let total = totals.entry(key).or_insert_with(|| ProjectTotal {
+                    project,
+                    total: Duration::zero(),
+                    own: Duration::zero(),
+                    session_count: 0,
+                });
+                total.total += session.duration;
+                total.session_count += 1;
+                if level + 1 == levels {
+                    total.own += session.duration;
+                }
+            }
+        }
+        totals.into_values().collect()
+    }
+
+    ///Only the sessions carrying `tag`, anomalies are kept as they are
+    pub fn tagged(mut self, tag: &str) -> Self {
+        self.sessions.retain(|session| session.has_tag(tag));
+        self
+    }
+}
+
+///A clock-in still waiting for its clock-out
+struct OpenSession<'a> {
+    clock_in: &'a Command,
+    paused: Duration,
+    paused_since: Option<DateTime<Utc>>,
+    notes: Vec<String>,
+    tags: Vec<String>,
+}
+
+impl<'a> OpenSession<'a> {
+    fn new(clock_in: &'a Command) -> Self {
+        let mut session = Self { clock_in, paused: Duration::zero(), paused_since: None, notes: Vec::new(), tags: Vec::new() };
+        session.annotate(clock_in);
+        session
+    }
+
+    ///Keep the command's note, if it has one, and any tags not already kept
+    fn annotate(&mut self, cmd: &Command) {
+        self.notes.extend(cmd.note.clone());
+        for tag in &cmd.tags {
+            if !self.tags.contains(tag) {
+                self.tags.push(tag.clone());
+            }
+        }
+    }
+
+    fn resume(&mut self, at: DateTime<Utc>) {
+        if let Some(since) = self.paused_since.take() {
+            self.paused += at - since;
+        }
+    }
+
+    ///The session as it stands at `at`, still open
+    fn measure(&self, at: DateTime<Utc>) -> Session {
+        let paused = match self.paused_since {
+            Some(since) => self.paused + (at - since),
+            None => self.paused,
+        };
+        Session {
+            task: self.clock_in.task.clone(),
+            start: self.clock_in.cmd_datetime,
+            end: None,
+            duration: at - self.clock_in.cmd_datetime - paused,
+            paused,
+            paused_since: self.paused_since,
+            notes: self.notes.clone(),
+            tags: self.tags.clone(),
+            stale_since: None,
+            auto_closed: false,
+        }
+    }
+
+    ///Clock out at `end`, a clock-out while paused ends the pause too
+    fn close(&self, end: DateTime<Utc>) -> Session {
+        Session {
+            end: Some(end),
+            paused_since: None,
+            ..self.measure(end)
+        }
+    }
+}
+
+///Durations serialize as whole seconds
+mod seconds {
+    use chrono::Duration;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(duration.num_seconds())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        Ok(Duration::seconds(i64::deserialize(deserializer)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::tests::create_test_cmd;
+
+    use super::*;
+
+    const TASK: &str = "session task";
+    const OTHER_TASK: &str = "other session task";
+
+    fn now() -> DateTime<Utc> {
+        "2022-01-31 20:00:00+00:00".parse().unwrap()
+    }
+
+    #[test]
+    fn test_pair_sessions() {
+        let cmds = vec![
+            create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 09:00:00+00:00"),
+            create_test_cmd(CommandType::ClockIn, OTHER_TASK, "2022-01-31 09:30:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, TASK, "2022-01-31 10:15:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, OTHER_TASK, "2022-01-31 11:00:00+00:00"),
+        ];
+        let list = SessionList::from_commands(&cmds, now());
+        assert!(list.anomalies.is_empty());
+        assert_eq!(list.sessions.len(), 2);
+        assert_eq!(list.sessions[0].task, TASK);
+        assert_eq!(list.sessions[0].duration, Duration::minutes(75));
+        assert_eq!(list.sessions[1].task, OTHER_TASK);
+        assert_eq!(list.sessions[1].duration, Duration::minutes(90));
+        assert_eq!(list.total_duration(), Duration::minutes(165));
+    }
+
+    #[test]
+    fn test_paused_time_excluded() {
+        let cmds = vec![
+            create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 09:00:00+00:00"),
+            create_test_cmd(CommandType::Pause, TASK, "2022-01-31 12:00:00+00:00"),
+            create_test_cmd(CommandType::Resume, TASK, "2022-01-31 13:00:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, TASK, "2022-01-31 17:00:00+00:00"),
+            //still paused at now
+            create_test_cmd(CommandType::ClockIn, OTHER_TASK, "2022-01-31 18:00:00+00:00"),
+            create_test_cmd(CommandType::Pause, OTHER_TASK, "2022-01-31 19:00:00+00:00"),
+        ];
+        let list = SessionList::from_commands(&cmds, now());
+        assert!(list.anomalies.is_empty());
+        assert_eq!(list.sessions[0].duration, Duration::hours(7));
+        assert_eq!(list.sessions[0].paused, Duration::hours(1));
+        assert!(!list.sessions[0].is_paused());
+        assert_eq!(list.sessions[1].duration, Duration::hours(1));
+        assert!(list.sessions[1].is_open());
+        assert!(list.sessions[1].is_paused());
+    }
+
+    #[test]
+    fn test_switch_closes_other_sessions() {
+        let cmds = vec![
+            create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 09:00:00+00:00"),
+            create_test_cmd(CommandType::Switch, OTHER_TASK, "2022-01-31 10:00:00+00:00"),
+            create_test_cmd(CommandType::Switch, TASK, "2022-01-31 11:00:00+00:00"),
+        ];
+        let list = SessionList::from_commands(&cmds, now());
+        assert!(list.anomalies.is_empty());
+        assert_eq!(list.sessions.len(), 3);
+        assert_eq!(list.sessions[0].end, Some(cmds[1].cmd_datetime));
+        assert_eq!(list.sessions[1].task, OTHER_TASK);
+        assert_eq!(list.sessions[1].duration, Duration::hours(1));
+        assert!(list.sessions[2].is_open());
+    }
+
+    #[test]
+    fn test_task_state_transitions() {
+        let state = [CommandType::ClockIn, CommandType::Pause, CommandType::Resume]
+            .iter()
+            .try_fold(TaskState::Idle, |state, cmd| state.apply(cmd));
+        assert_eq!(state, Some(TaskState::Tracking));
+        assert_eq!(TaskState::Paused.apply(&CommandType::ClockOut), Some(TaskState::Idle));
+        assert_eq!(TaskState::Idle.apply(&CommandType::Pause), None);
+        assert_eq!(TaskState::Tracking.apply(&CommandType::Resume), None);
+        assert!(TaskState::Paused.is_open());
+        assert_eq!(TaskState::replay(&[CommandType::ClockOut, CommandType::ClockIn, CommandType::Pause]), TaskState::Paused);
+        assert_eq!(TaskState::replay(&[CommandType::ClockIn, CommandType::Pause, CommandType::ClockIn]), TaskState::Tracking);
+    }
+
+    #[test]
+    fn test_task_totals() {
+        let cmds = vec![
+            create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 09:00:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, TASK, "2022-01-31 10:00:00+00:00"),
+            create_test_cmd(CommandType::ClockIn, OTHER_TASK, "2022-01-31 10:00:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, OTHER_TASK, "2022-01-31 10:30:00+00:00"),
+            create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 19:00:00+00:00"),
+        ];
+        let totals = SessionList::from_commands(&cmds, now()).task_totals();
+        assert_eq!(totals.len(), 2);
+        assert_eq!(totals[0].task, OTHER_TASK);
+        assert_eq!(totals[0].total, Duration::minutes(30));
+        assert_eq!(totals[0].open_since, None);
+        assert_eq!(totals[1].task, TASK);
+        assert_eq!(totals[1].session_count, 2);
+        assert_eq!(totals[1].total, Duration::hours(2));
+        assert_eq!(totals[1].first_activity, cmds[0].cmd_datetime);
+        assert_eq!(totals[1].last_activity, cmds[4].cmd_datetime);
+        assert_eq!(totals[1].open_since, Some(cmds[4].cmd_datetime));
+    }
+
+    #[test]
+    fn test_tag_totals() {
+        let mut meeting = create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 09:00:00+00:00");
+        meeting.tags = vec!["meeting".to_string(), "billable".to_string()];
+        let mut review = create_test_cmd(CommandType::ClockIn, OTHER_TASK, "2022-01-31 11:00:00+00:00");
+        review.tags = vec!["billable".to_string()];
+        let mut review_out = create_test_cmd(CommandType::ClockOut, OTHER_TASK, "2022-01-31 11:30:00+00:00");
+        review_out.tags = vec!["billable".to_string(), "review".to_string()];
+        let cmds = vec![
+            meeting,
+            create_test_cmd(CommandType::ClockOut, TASK, "2022-01-31 10:00:00+00:00"),
+            review,
+            review_out,
+            create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 19:00:00+00:00"),
+        ];
+        let list = SessionList::from_commands(&cmds, now());
+        assert_eq!(list.sessions[1].tags, vec!["billable".to_string(), "review".to_string()]);
+
+        let totals: Vec<(Option<String>, Duration, usize)> = list.tag_totals().into_iter()
+            .map(|total| (total.tag, total.total, total.session_count))
+            .collect();
+        assert_eq!(totals, vec![
+            (Some("billable".to_string()), Duration::minutes(90), 2),
+            (Some("meeting".to_string()), Duration::hours(1), 1),
+            (Some("review".to_string()), Duration::minutes(30), 1),
+            (None, Duration::hours(1), 1),
+        ]);
+
+        let billable = list.tagged("billable");
+        assert_eq!(billable.sessions.len(), 2);
+        assert_eq!(billable.total_duration(), Duration::minutes(90));
+    }
+
+    #[test]
+    fn test_project_totals() {
+        let cmds = vec![
+            create_test_cmd(CommandType::ClockIn, "clientA/website/login-bug", "2022-01-31 09:00:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, "clientA/website/login-bug", "2022-01-31 10:00:00+00:00"),
+            create_test_cmd(CommandType::ClockIn, "clientA/website", "2022-01-31 10:00:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, "clientA/website", "2022-01-31 10:30:00+00:00"),
+            create_test_cmd(CommandType::ClockIn, "clientA/billing", "2022-01-31 11:00:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, "clientA/billing", "2022-01-31 11:15:00+00:00"),
+            create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 12:00:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, TASK, "2022-01-31 13:00:00+00:00"),
+        ];
+        let totals: Vec<(String, Duration, Duration, usize)> = SessionList::from_commands(&cmds, now()).project_totals().into_iter()
+            .map(|total| (total.project.path, total.total, total.own, total.session_count))
+            .collect();
+        assert_eq!(totals, vec![
+            ("clientA".to_string(), Duration::minutes(105), Duration::zero(), 3),
+            ("clientA/billing".to_string(), Duration::minutes(15), Duration::minutes(15), 1),
+            ("clientA/website".to_string(), Duration::minutes(90), Duration::minutes(30), 2),
+            ("clientA/website/login-bug".to_string(), Duration::hours(1), Duration::hours(1), 1),
+            (TASK.to_string(), Duration::hours(1), Duration::hours(1), 1),
+        ]);
+    }
+
+    #[test]
+    fn test_open_session_measured_to_now() {
+        let cmds = vec![create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 18:30:00+00:00")];
+        let list = SessionList::from_commands(&cmds, now());
+        assert_eq!(list.sessions.len(), 1);
+        assert!(list.sessions[0].is_open());
+        assert_eq!(list.sessions[0].duration, Duration::minutes(90));
+        assert_eq!(list.open_sessions().count(), 1);
+    }
+
+    #[test]
+    fn test_flag_stale() {
+        let mut auto_out = create_test_cmd(CommandType::ClockOut, OTHER_TASK, "2022-01-31 17:00:00+00:00");
+        auto_out.system = true;
+        let cmds = vec![
+            create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 09:00:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, TASK, "2022-01-31 12:00:00+00:00"),
+            create_test_cmd(CommandType::ClockIn, OTHER_TASK, "2022-01-31 09:30:00+00:00"),
+            auto_out,
+            //forgotten, still open at now
+            create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 13:00:00+00:00"),
+        ];
+        let policy = StalePolicy { max_duration: Some(Duration::hours(4)), ..Default::default() };
+        let list = SessionList::from_commands(&cmds, now()).flag_stale(&policy, &Utc);
+        assert_eq!(list.sessions.len(), 3);
+        assert!(!list.sessions[0].needs_review());
+        assert!(list.sessions[1].auto_closed);
+        assert_eq!(list.sessions[1].stale_since, Some("2022-01-31 13:30:00+00:00".parse().unwrap()));
+        assert_eq!(list.sessions[2].stale_since, Some("2022-01-31 17:00:00+00:00".parse().unwrap()));
+        assert_eq!(list.needing_review().count(), 2);
+
+        let list = SessionList::from_commands(&cmds, now()).flag_stale(&StalePolicy::default(), &Utc);
+        assert_eq!(list.needing_review().map(|session| session.task.as_str()).collect::<Vec<_>>(), vec![OTHER_TASK]);
+    }
+
+    #[test]
+    fn test_anomalies_reported() {
+        let cmds = vec![
+            create_test_cmd(CommandType::ClockOut, TASK, "2022-01-31 08:00:00+00:00"),
+            create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 09:00:00+00:00"),
+            create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 10:00:00+00:00"),
+            create_test_cmd(CommandType::ClockOut, TASK, "2022-01-31 11:00:00+00:00"),
+        ];
+        let list = SessionList::from_commands(&cmds, now());
+        assert_eq!(list.sessions.len(), 1);
+        assert_eq!(list.sessions[0].duration, Duration::hours(1));
+        assert_eq!(list.anomalies, vec![
+            Anomaly::OrphanClockOut(cmds[0].clone()),
+            Anomaly::UnmatchedClockIn(cmds[1].clone()),
+        ]);
+
+        let cmds = vec![
+            create_test_cmd(CommandType::Pause, TASK, "2022-01-31 08:00:00+00:00"),
+            create_test_cmd(CommandType::ClockIn, TASK, "2022-01-31 09:00:00+00:00"),
+            create_test_cmd(CommandType::Resume, TASK, "2022-01-31 10:00:00+00:00"),
+        ];
+        let list = SessionList::from_commands(&cmds, now());
+        assert_eq!(list.anomalies, vec![
+            Anomaly::OrphanPause(cmds[0].clone()),
+            Anomaly::OrphanResume(cmds[2].clone()),
+        ]);
+    }
+}
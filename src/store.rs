@@ -0,0 +1,394 @@
+use std::cell::{Cell, RefCell};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::journal::JournalEntry;
+use crate::command::{Command, CommandType};
+use crate::error::ClockError;
+use crate::integrity;
+use crate::project::{self, Project};
+use crate::session::{Session, SessionList};
+
+///Which commands to list, every limit is optional
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CommandFilter {
+    ///Only commands at or after this time
+    pub start: Option<DateTime<Utc>>,
+    ///Only commands at or before this time
+    pub end: Option<DateTime<Utc>>,
+    ///Only commands for this task
+    pub task: Option<String>,
+    ///Only commands with a note containing this, ignoring ASCII case
+    pub note_contains: Option<String>,
+    ///Only commands carrying every one of these tags
+    pub tags: Vec<String>,
+    ///Only commands for tasks in this project or any below it, eg `clientA` takes in `clientA/website/login-bug`
+    pub project: Option<String>,
+}
+
+impl CommandFilter {
+    pub fn new(start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>, task: Option<&str>) -> Self {
+        Self {
+            start,
+            end,
+            task: task.map(String::from),
+            note_contains: None,
+            tags: Vec::new(),
+            project: None,
+        }
+    }
+
+    ///Only commands with a note containing `text`
+    pub fn with_note_search(mut self, text: &str) -> Self {
+        self.note_contains = Some(text.to_string());
+        self
+    }
+
+    ///Only commands tagged `tag`, on top of any tags already asked for
+    pub fn with_tag(mut self, tag: &str) -> Self {
+        self.tags.push(tag.to_string());
+        self
+    }
+
+    ///Only commands for tasks within the project's subtree
+    pub fn with_project(mut self, path: &str) -> Self {
+        self.project = Some(path.to_string());
+        self
+    }
+
+    ///Does the command pass every limit
+    pub fn matches(&self, cmd: &Command) -> bool {
+        self.start.is_none_or(|start| cmd.cmd_datetime >= start)
+            && self.end.is_none_or(|end| cmd.cmd_datetime <= end)
+            && self.task.as_ref().is_none_or(|task| *task == cmd.task)
+            && self.note_contains.as_ref().is_none_or(|text| cmd.note.as_ref()
+                .is_some_and(|note| note.to_ascii_lowercase().contains(&text.to_ascii_lowercase())))
+            && self.tags.iter().all(|tag| cmd.tags.contains(tag))
+            && self.project.as_ref().is_none_or(|root| project::in_subtree(&cmd.task, root))
+    }
+}
+
+///Where ClockRuster keeps its commands
+/// Everything past storing and fetching commands - policies, switching, sessions - lives in ClockRuster
+pub trait ClockStore {
+    ///Record a command, returning the id it is kept under
+    /// Any id the command already has is ignored, and an id is never handed out twice, not even once its command is deleted
+    fn insert(&self, cmd: &Command) -> Result<i64, ClockError>;
+
+    ///The command kept under this id, if there is one
+    fn get(&self, id: i64) -> Result<Option<Command>, ClockError>;
+
+    ///Replace the command kept under cmd's id with cmd
+    /// Fails with ClockError::NoSuchCommand if nothing is kept under it
+    fn update(&self, cmd: &Command) -> Result<(), ClockError>;
+
+    ///Forget the command kept under this id
+    /// Fails with ClockError::NoSuchCommand if nothing is kept under it
+    fn delete(&self, id: i64) -> Result<(), ClockError>;
+
+    ///Put a command back under the id it had, for undoing a delete
+    /// The id has to be free, which it is when everything after the delete has been undone first
+    fn restore(&self, cmd: &Command) -> Result<(), ClockError>;
+
+    ///Add an entry to the end of the journal, returning the id it is kept under
+    fn append_journal(&self, entry: &JournalEntry) -> Result<i64, ClockError>;
+
+    ///Every journal entry, oldest first
+    fn journal(&self) -> Result<Vec<JournalEntry>, ClockError>;
+
+    ///Every command, in id order, with the chain hash kept for it, see integrity::link
+    /// Storing, changing or removing a command reseals the links from it on
+    fn chain(&self) -> Result<Vec<(Command, Option<String>)>, ClockError>;
+
+    ///Number of times a command type was recorded for the task
+    fn count(&self, cmd_type: &CommandType, task: &str) -> Result<i64, ClockError>;
+
+    ///Commands passing the filter, ordered by task and then time
+    fn list(&self, filter: &CommandFilter) -> Result<Vec<Command>, ClockError>;
+
+    ///Run f so that either everything it stores is kept, or, if it fails, none of it is
+    /// Has to nest, f may well call atomically again
+    fn atomically<T, F: FnOnce() -> Result<T, ClockError>>(&self, f: F) -> Result<T, ClockError>;
+
+    ///Every project any recorded task is filed under, each level of its path included
+    /// Ordered by path, so each project comes straight after the one above it
+    fn projects(&self) -> Result<Vec<Project>, ClockError>;
+
+    ///Sessions from the commands passing the filter, open ones measured up to now
+    fn sessions(&self, filter: &CommandFilter, now: DateTime<Utc>) -> Result<SessionList, ClockError> {
+        Ok(SessionList::from_commands(&self.list(filter)?, now))
+    }
+
+    ///Sessions on every task still clocked in, paused or not
+    fn open_sessions(&self, now: DateTime<Utc>) -> Result<Vec<Session>, ClockError> {
+        Ok(self.sessions(&CommandFilter::default(), now)?.sessions.into_iter()
+            .filter(|session| session.is_open())
+            .collect())
+    }
+}
+
+///Keeps commands in memory only, for tests and for embedding where nothing needs to outlive the process
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    cmds: RefCell<Vec<Command>>,
+    journal: RefCell<Vec<JournalEntry>>,
+    ///Last id handed out, ids are never reused even when a command is deleted
+    last_id: Cell<i64>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ClockStore for MemoryStore {
+    fn insert(&self, cmd: &Command) -> Result<i64, ClockError> {
+        let id = self.last_id.get() + 1;
+        self.last_id.set(id);
+        let mut cmd = cmd.clone();
+        cmd.id = Some(id);
+        self.cmds.borrow_mut().push(cmd);
+        Ok(id)
+    }
+
+    fn get(&self, id: i64) -> Result<Option<Command>, ClockError> {
+        Ok(self.cmds.borrow().iter().find(|cmd| cmd.id == Some(id)).cloned())
+    }
+
+    fn update(&self, cmd: &Command) -> Result<(), ClockError> {
+        let id = cmd.id.ok_or_else(|| ClockError::CorruptData(format!("no id to update {} with", cmd)))?;
+        match self.cmds.borrow_mut().iter_mut().find(|stored| stored.id == Some(id)) {
+            Some(stored) => {
+                *stored = cmd.clone();
+                Ok(())
+            }
+            None => Err(ClockError::NoSuchCommand(id)),
+        }
+    }
+
+    fn delete(&self, id: i64) -> Result<(), ClockError> {
+        let mut cmds = self.cmds.borrow_mut();
+        let before = cmds.len();
+        cmds.retain(|cmd| cmd.id != Some(id));
+        if cmds.len() == before {
+            return Err(ClockError::NoSuchCommand(id));
+        }
+        Ok(())
+    }
+
+    fn restore(&self, cmd: &Command) -> Result<(), ClockError> {
+        let id = cmd.id.ok_or_else(|| ClockError::CorruptData(format!("no id to restore {} under", cmd)))?;
+        if self.get(id)?.is_some() {
+            return Err(ClockError::CorruptData(format!("can't restore {}, id {} is taken", cmd, id)));
+        }
+        self.last_id.set(self.last_id.get().max(id));
+        self.cmds.borrow_mut().push(cmd.clone());
+        Ok(())
+    }
+
+    fn append_journal(&self, entry: &JournalEntry) -> Result<i64, ClockError> {
+        let mut journal = self.journal.borrow_mut();
+        let id = journal.len() as i64 + 1;
+        journal.push(JournalEntry { id: Some(id), ..entry.clone() });
+        Ok(id)
+    }
+
+    fn journal(&self) -> Result<Vec<JournalEntry>, ClockError> {
+        Ok(self.journal.borrow().clone())
+    }
+
+    ///Nothing else can get at the commands, so the links are made as they are asked for
+    fn chain(&self) -> Result<Vec<(Command, Option<String>)>, ClockError> {
+        let mut cmds = self.cmds.borrow().clone();
+        cmds.sort_by_key(|cmd| cmd.id);
+        let links = integrity::seal(&cmds);
+        Ok(cmds.into_iter().zip(links.into_iter().map(Some)).collect())
+    }
+
+    fn count(&self, cmd_type: &CommandType, task: &str) -> Result<i64, ClockError> {
+        Ok(self.cmds.borrow().iter()
+            .filter(|cmd| cmd.command == *cmd_type && cmd.task == task)
+            .count() as i64)
+    }
+
+    fn list(&self, filter: &CommandFilter) -> Result<Vec<Command>, ClockError> {
+        let mut cmds: Vec<Command> = self.cmds.borrow().iter()
+            .filter(|cmd| filter.matches(cmd))
+            .cloned()
+            .collect();
+        cmds.sort_by(|a, b| a.task.cmp(&b.task).then(a.cmd_datetime.cmp(&b.cmd_datetime)));
+        Ok(cmds)
+    }
+
+    fn projects(&self) -> Result<Vec<Project>, ClockError> {
+        let mut projects: Vec<Project> = self.cmds.borrow().iter()
+            .flat_map(|cmd| project::ancestors(&cmd.task))
+            .collect();
+        projects.sort_by(|a, b| project::segments(&a.path).cmp(&project::segments(&b.path)));
+        projects.dedup();
+        Ok(projects)
+    }
+
+    fn atomically<T, F: FnOnce() -> Result<T, ClockError>>(&self, f: F) -> Result<T, ClockError> {
+        let snapshot = (self.cmds.borrow().clone(), self.journal.borrow().clone());
+        let result = f();
+        if result.is_err() {
+            *self.cmds.borrow_mut() = snapshot.0;
+            *self.journal.borrow_mut() = snapshot.1;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::db::tests::{create_test_cmd, temp_db, TEST_TASK, TEST_TASK_2};
+    use crate::integrity::IntegrityReport;
+    use crate::journal::{Change, EntryKind};
+    use crate::db::SqliteStore;
+
+    use super::*;
+
+    ///Open sessions the store keeps are the ones pairing up every command gives
+    fn check_open_sessions<S: ClockStore>(store: &S, now: DateTime<Utc>) {
+        let mut paired: Vec<Session> = store.sessions(&CommandFilter::default(), now).unwrap().open_sessions().cloned().collect();
+        paired.sort_by(|a, b| (a.start, &a.task).cmp(&(b.start, &b.task)));
+        assert_eq!(store.open_sessions(now).unwrap(), paired);
+    }
+
+    ///What every store has to do, whatever it keeps commands in
+    pub fn check_store<S: ClockStore>(store: &S) {
+        store.insert(&create_test_cmd(CommandType::ClockIn, TEST_TASK_2, "2022-01-31 09:00:00+00:00")).unwrap();
+        let mut system_out = create_test_cmd(CommandType::ClockOut, TEST_TASK, "2022-01-31 11:00:00+00:00");
+        system_out.system = true;
+        system_out.id = Some(store.insert(&system_out).unwrap());
+        assert_eq!(store.get(system_out.id.unwrap()).unwrap(), Some(system_out));
+        let mut noted = create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 10:00:00+00:00");
+        noted.note = Some("Fixed the 100% bug".to_string());
+        noted.tags = vec!["billable".to_string(), "client work".to_string()];
+        noted.id = Some(store.insert(&noted).unwrap());
+        assert_eq!(store.count(&CommandType::ClockIn, TEST_TASK).unwrap(), 1);
+        assert_eq!(store.count(&CommandType::Pause, TEST_TASK).unwrap(), 0);
+
+        let all = store.list(&CommandFilter::default()).unwrap();
+        let order: Vec<(&str, CommandType)> = all.iter().map(|cmd| (cmd.task.as_str(), cmd.command.clone())).collect();
+        assert_eq!(order, vec![
+            (TEST_TASK, CommandType::ClockIn),
+            (TEST_TASK, CommandType::ClockOut),
+            (TEST_TASK_2, CommandType::ClockIn),
+        ]);
+        let start = "2022-01-31 10:00:00+00:00".parse().unwrap();
+        assert_eq!(store.list(&CommandFilter::new(Some(start), None, None)).unwrap().len(), 2);
+        assert_eq!(store.list(&CommandFilter::new(None, Some(start), Some(TEST_TASK))).unwrap().len(), 1);
+        let found = store.list(&CommandFilter::default().with_note_search("the 100%")).unwrap();
+        assert_eq!(found, vec![noted.clone()]);
+        assert!(store.list(&CommandFilter::default().with_note_search("fixed_the")).unwrap().is_empty());
+        let billable = CommandFilter::default().with_tag("billable");
+        assert_eq!(store.list(&billable).unwrap(), vec![noted.clone()]);
+        assert_eq!(store.list(&billable.clone().with_tag("client work")).unwrap(), vec![noted.clone()]);
+        assert!(store.list(&billable.with_tag("meeting")).unwrap().is_empty());
+
+        store.insert(&create_test_cmd(CommandType::ClockIn, "clientA / website/login-bug", "2022-01-31 08:00:00+00:00")).unwrap();
+        store.insert(&create_test_cmd(CommandType::ClockIn, "clientA/website", "2022-01-31 08:30:00+00:00")).unwrap();
+        store.insert(&create_test_cmd(CommandType::ClockIn, "clientAB", "2022-01-31 08:45:00+00:00")).unwrap();
+        let in_project = |path: &str| store.list(&CommandFilter::default().with_project(path)).unwrap().len();
+        assert_eq!(in_project("clientA"), 2);
+        assert_eq!(in_project("clientA/ website"), 2);
+        assert_eq!(in_project("clientA/website/login-bug"), 1);
+        assert_eq!(in_project("clientA/web"), 0);
+        let paths: Vec<String> = store.projects().unwrap().into_iter().map(|project| project.path).collect();
+        assert_eq!(paths, vec![TEST_TASK, TEST_TASK_2, "clientA", "clientA/website", "clientA/website/login-bug", "clientAB"]);
+
+        //failing halfway keeps nothing, even nested
+        let failed: Result<(), ClockError> = store.atomically(|| {
+            store.atomically(|| store.insert(&create_test_cmd(CommandType::ClockOut, TEST_TASK_2, "2022-01-31 12:00:00+00:00")))?;
+            Err(ClockError::CorruptData("on purpose".to_string()))
+        });
+        assert!(failed.is_err());
+        assert_eq!(store.list(&CommandFilter::default()).unwrap().len(), 6);
+
+        let now = "2022-01-31 12:00:00+00:00".parse().unwrap();
+        let open = store.open_sessions(now).unwrap();
+        assert_eq!(open.len(), 4);
+        assert!(open.iter().any(|session| session.task == TEST_TASK_2));
+        check_open_sessions(store, now);
+
+        //ids are what list hands back, and stay with the command through an update
+        let id = noted.id.unwrap();
+        assert_eq!(store.get(id).unwrap(), Some(noted.clone()));
+        let mut moved = noted.clone();
+        moved.task = TEST_TASK_2.to_string();
+        moved.tags = vec!["meeting".to_string()];
+        store.update(&moved).unwrap();
+        assert_eq!(store.get(id).unwrap(), Some(moved.clone()));
+        assert_eq!(store.list(&CommandFilter::default().with_tag("meeting")).unwrap(), vec![moved.clone()]);
+        assert!(store.list(&CommandFilter::default().with_tag("billable")).unwrap().is_empty());
+        store.delete(id).unwrap();
+        assert_eq!(store.get(id).unwrap(), None);
+        assert!(matches!(store.delete(id), Err(ClockError::NoSuchCommand(gone)) if gone == id));
+        assert!(matches!(store.update(&moved), Err(ClockError::NoSuchCommand(_))));
+        assert_ne!(store.insert(&noted).unwrap(), id);
+        //not even the newest one
+        let newest = store.insert(&noted).unwrap();
+        store.delete(newest).unwrap();
+        assert!(store.insert(&noted).unwrap() > newest);
+        store.restore(&moved).unwrap();
+        assert_eq!(store.get(id).unwrap(), Some(moved.clone()));
+        assert!(store.restore(&moved).is_err());
+        check_open_sessions(store, now);
+        let mut paused = create_test_cmd(CommandType::Pause, "clientAB", "2022-01-31 11:00:00+00:00");
+        paused.note = Some("lunch".to_string());
+        store.insert(&paused).unwrap();
+        check_open_sessions(store, now);
+        store.insert(&create_test_cmd(CommandType::Switch, "clientA/website", "2022-01-31 11:30:00+00:00")).unwrap();
+        check_open_sessions(store, now);
+        assert_eq!(store.open_sessions(now).unwrap().len(), 1);
+        let mut noted_open = create_test_cmd(CommandType::Note, "clientA/website", "2022-01-31 11:35:00+00:00");
+        noted_open.note = Some("second look".to_string());
+        noted_open.tags = vec!["review".to_string()];
+        for cmd in [noted_open,
+                    create_test_cmd(CommandType::Pause, "clientA/website", "2022-01-31 11:40:00+00:00"),
+                    create_test_cmd(CommandType::Resume, "clientA/website", "2022-01-31 11:50:00+00:00"),
+                    //before the pause, so it pairs up differently than it would last
+                    create_test_cmd(CommandType::ClockIn, "clientA/website", "2022-01-31 11:38:00+00:00"),
+                    create_test_cmd(CommandType::ClockIn, TEST_TASK, "2022-01-31 11:55:00+00:00")] {
+            store.insert(&cmd).unwrap();
+            check_open_sessions(store, now);
+        }
+
+        let entry = JournalEntry {
+            id: None,
+            kind: EntryKind::Record,
+            target: None,
+            at: now,
+            description: "delete".to_string(),
+            changes: vec![Change::delete(moved.clone()), Change::insert(noted.clone())],
+        };
+        let entry_id = store.append_journal(&entry).unwrap();
+        let undo = JournalEntry { kind: EntryKind::Undo, target: Some(entry_id), changes: Vec::new(), ..entry.clone() };
+        let undo_id = store.append_journal(&undo).unwrap();
+        assert_eq!(store.journal().unwrap(), vec![
+            JournalEntry { id: Some(entry_id), ..entry },
+            JournalEntry { id: Some(undo_id), ..undo },
+        ]);
+
+        //every change above left the chain whole
+        let chain = store.chain().unwrap();
+        assert!(chain.windows(2).all(|pair| pair[0].0.id < pair[1].0.id));
+        assert_eq!(chain.len(), store.list(&CommandFilter::default()).unwrap().len());
+        assert!(IntegrityReport::verify(&chain).is_intact());
+    }
+
+    #[test]
+    fn test_memory_store() {
+        check_store(&MemoryStore::new());
+    }
+
+    #[test]
+    fn test_sqlite_store() {
+        let (_dir, db_string) = temp_db();
+        check_store(&SqliteStore::open(&db_string));
+    }
+}